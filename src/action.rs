@@ -1,7 +1,17 @@
+use crate::cancel::JobControl;
+use crate::capture::Capture;
 use crate::config::Behavior;
-use crate::record_posts::{DBInsertError, PostInDB, PostRecordRequest, PostRecordScrape};
+use crate::disclosure::{DisclosureError, DisclosurePolicy};
+use crate::dry_run::{StepKind, StepLog};
+use crate::failure::watch_console;
+use crate::record_posts::{
+    DBInsertError, PostInDB, PostRecordRequest, PostRecordScrape, PostRef,
+};
+use crate::rate_limit::{limited_get, OpKind};
+use crate::retry::{click_not_delivered, with_retry, with_retry_if};
 use crate::search::Search;
-use crate::utils::rand_num_wait;
+use crate::thread::ThreadRecorder;
+use crate::utils::{bot_error, rand_num_wait};
 use futures::executor::block_on;
 use mongodb::Database;
 use serde::{Deserialize, Serialize};
@@ -9,8 +19,7 @@ use serde_json::from_str;
 use std::collections::HashSet;
 use std::result::Result;
 use thirtyfour::prelude::*;
-use tokio;
-use tokio::time::{sleep, Duration};
+use tokio::time::Duration;
 
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
 pub enum PostNumber {
@@ -18,6 +27,24 @@ pub enum PostNumber {
     Last,
     Nth(u32),
 }
+
+const TEXTAREA_XPATH: &str = "//*[@data-testid = \"tweetTextarea_0\"]";
+const LAST_TEXTAREA_XPATH: &str = "//*[@data-testid = \"tweetTextarea_0\"][last()]";
+const TWEET_BUTTON_XPATH: &str = "//*[@data-testid = \"tweetButtonInline\"]";
+const FILE_INPUT_XPATH: &str = "//input[@data-testid = \"fileInput\"]";
+const RETWEET_CONFIRM_XPATH: &str = "//*[@data-testid = \"retweetConfirm\"]";
+const COMPOSE_LINK_XPATH: &str = "//a[@href = \"/compose/tweet/\"][last()]";
+const ARTICLE_XPATH: &str = "//article[@data-testid = \"tweet\"]";
+
+impl PostNumber {
+    pub fn xpath(&self, test_id: &str) -> String {
+        match self {
+            PostNumber::First => format!("//*[@data-testid = \"{}\"]", test_id),
+            PostNumber::Last => format!("//*[@data-testid = \"{}\"][last()]", test_id),
+            PostNumber::Nth(num) => format!("(//*[@data-testid = \"{}\"])[{}]", test_id, num),
+        }
+    }
+}
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
 pub enum Action {
     PostText(TextPost),
@@ -29,15 +56,25 @@ pub enum Action {
     CommentText(TextComment),
     CommentImage(ImageComment),
     RecordPost(PostRecorderMode),
+    Capture(Capture),
 }
 
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
 pub enum PostRecorderMode {
     Request(PostRecordRequest),
     Scrape(PostRecordScrape),
+    Thread(ThreadRecorder),
 }
 
 impl PostRecorderMode {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PostRecorderMode::Request(_) => "Request",
+            PostRecorderMode::Scrape(_) => "Scrape",
+            PostRecorderMode::Thread(_) => "Thread",
+        }
+    }
+
     pub async fn call(
         &mut self,
         db: &Database,
@@ -50,6 +87,9 @@ impl PostRecorderMode {
             PostRecorderMode::Scrape(object) => {
                 object.post_in_db(db, driver).await?;
             }
+            PostRecorderMode::Thread(object) => {
+                object.post_in_db(db, driver).await?;
+            }
         }
 
         Ok(())
@@ -150,34 +190,168 @@ impl FromText for RtQuotePost  {
 
 
 impl Action {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Action::PostText(_) => "PostText",
+            Action::PostImage(_) => "PostImage",
+            Action::LikePost(_) => "LikePost",
+            Action::SearchTwitter(_) => "SearchTwitter",
+            Action::Retweet(_) => "Retweet",
+            Action::QuoteRetweet(_) => "QuoteRetweet",
+            Action::CommentText(_) => "CommentText",
+            Action::CommentImage(_) => "CommentImage",
+            Action::RecordPost(_) => "RecordPost",
+            Action::Capture(_) => "Capture",
+        }
+    }
+
+    pub fn is_publishing(&self) -> bool {
+        matches!(
+            self,
+            Action::PostText(_)
+                | Action::PostImage(_)
+                | Action::CommentText(_)
+                | Action::CommentImage(_)
+                | Action::QuoteRetweet(_)
+        )
+    }
+
+    /// Publishing actions are held in the approval queue until a person signs them off.
+    pub fn needs_approval(&self) -> bool {
+        self.is_publishing()
+    }
+
+    /// Returns the action with the disclosure label on everything it would publish.
+    pub fn disclosed(&self, policy: &DisclosurePolicy) -> Result<Action, Box<DisclosureError>> {
+        let mut action = self.clone();
+
+        match &mut action {
+            Action::PostText(object) => {
+                object.content = policy.apply(object.content.as_str())?;
+            }
+            Action::PostImage(object) => {
+                object.text = Some(policy.apply(object.text.as_deref().unwrap_or_default())?);
+            }
+            Action::QuoteRetweet(object) => {
+                object.text = Some(policy.apply(object.text.as_deref().unwrap_or_default())?);
+            }
+            Action::CommentText(object) => {
+                object.text = policy.apply(object.text.as_str())?;
+            }
+            Action::CommentImage(object) => {
+                object.text = Some(policy.apply(object.text.as_deref().unwrap_or_default())?);
+            }
+            _ => {}
+        }
+
+        Ok(action)
+    }
+
+    pub fn render(&self) -> String {
+        match self {
+            Action::PostText(object) => format!("post on {}: {}", object.url, object.content),
+            Action::PostImage(object) => format!(
+                "post image {}: {}",
+                object.path,
+                object.text.clone().unwrap_or_default()
+            ),
+            Action::LikePost(object) => format!("like {:?} post on {}", object.number, object.url),
+            Action::SearchTwitter(object) => format!("search {}", object.clone().format_url()),
+            Action::Retweet(object) => {
+                format!("retweet {:?} post on {}", object.number, object.url)
+            }
+            Action::QuoteRetweet(object) => format!(
+                "quote {:?} post on {}: {}",
+                object.number,
+                object.url,
+                object.text.clone().unwrap_or_default()
+            ),
+            Action::CommentText(object) => format!("reply on {}: {}", object.url, object.text),
+            Action::CommentImage(object) => format!(
+                "reply on {} with image {}: {}",
+                object.url,
+                object.path,
+                object.text.clone().unwrap_or_default()
+            ),
+            Action::RecordPost(object) => format!("record posts ({})", object.kind()),
+            Action::Capture(object) => format!("capture {}", object.post().url()),
+        }
+    }
+
+    pub fn target(&self) -> Option<PostRef> {
+        match self {
+            Action::PostText(object) => PostRef::from_url(object.url.as_str()),
+            Action::LikePost(object) | Action::Retweet(object) => {
+                PostRef::from_url(object.url.as_str())
+            }
+            Action::QuoteRetweet(object) => PostRef::from_url(object.url.as_str()),
+            Action::CommentText(object) => PostRef::from_url(object.url.as_str()),
+            Action::CommentImage(object) => PostRef::from_url(object.url.as_str()),
+            Action::RecordPost(PostRecorderMode::Thread(object)) => Some(object.post().clone()),
+            Action::Capture(object) => Some(object.post().clone()),
+            Action::PostImage(_) | Action::SearchTwitter(_) | Action::RecordPost(_) => None,
+        }
+    }
+
+    /// The text the action would type, if it types any.
+    pub fn content(&self) -> Option<String> {
+        match self {
+            Action::PostText(object) => Some(object.content.clone()),
+            Action::PostImage(object) => object.text.clone(),
+            Action::QuoteRetweet(object) => object.text.clone(),
+            Action::CommentText(object) => Some(object.text.clone()),
+            Action::CommentImage(object) => object.text.clone(),
+            _ => None,
+        }
+    }
+
     pub async fn call(
         self,
         driver: &WebDriver,
         behavior: &Behavior,
         db: &Database,
+        log: &mut StepLog,
+        stop: &JobControl,
     ) -> WebDriverResult<()> {
         match self.clone() {
-            Action::PostText(object) => self.post_text(driver, object, behavior).await?,
+            Action::PostText(object) => {
+                self.post_text(driver, object, behavior, log, stop).await?;
+            }
             Action::PostImage(object) => {
-                self.post_image(driver, object, behavior).await?;
+                self.post_image(driver, object, behavior, log, stop).await?;
+            }
+            Action::LikePost(object) => {
+                self.like_post(driver, object, behavior, log, stop).await?;
+            }
+            Action::Retweet(object) => {
+                self.retweet_post(driver, object, behavior, log, stop).await?;
             }
-            Action::LikePost(object) => self.like_post(driver, object, behavior).await?,
-            Action::Retweet(object) => self.retweet_post(driver, object, behavior).await?,
             Action::QuoteRetweet(object) => {
-                self.quote_retweet_post(driver, object, behavior).await?;
+                self.quote_retweet_post(driver, object, behavior, log, stop).await?;
             }
             Action::CommentText(object) => {
-                self.comment_text(driver, object, behavior).await?;
+                self.comment_text(driver, object, behavior, log, stop).await?;
             }
             Action::CommentImage(object) => {
-                self.comment_image(driver, object, behavior).await?;
+                self.comment_image(driver, object, behavior, log, stop).await?;
             }
             Action::SearchTwitter(object) => {
-                self.search_site(driver, object, behavior).await?;
+                self.search_site(driver, object, behavior, log, stop).await?;
             }
             Action::RecordPost(object) => {
-                let mut clone_object = object.clone();
-                let _ = clone_object.call(db, driver).await.unwrap();
+                if log.record(StepKind::Record, object.kind(), None) {
+                    let mut clone_object = object.clone();
+                    stop.guard(async {
+                        clone_object
+                            .call(db, driver)
+                            .await
+                            .map_err(|e| bot_error(e.to_string()))
+                    })
+                    .await?;
+                }
+            }
+            Action::Capture(object) => {
+                self.capture_post(driver, object, behavior, log, stop).await?;
             }
         }
 
@@ -189,27 +363,20 @@ impl Action {
         driver: &WebDriver,
         object: TextPost,
         behavior: &Behavior,
+        log: &mut StepLog,
+        stop: &JobControl,
     ) -> WebDriverResult<()> {
-        driver.get(object.url).await?;
+        open_url(driver, object.url, OpKind::Post, behavior, log, stop).await?;
 
-        let elem_ta = driver
-            .find_element(By::XPath("//*[@data-testid = \"tweetTextarea_0\"]"))
-            .await?;
+        let elem_ta = find_xpath(driver, TEXTAREA_XPATH, behavior, log, stop).await?;
 
-        let chars = object.content.chars();
+        type_text(&elem_ta, object.content.as_str(), log, stop).await?;
 
-        for char in chars {
-            elem_ta.send_keys(char).await?;
-            sleep(Duration::from_millis(rand_num_wait().into())).await;
-        }
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+        let elem_btn = find_xpath(driver, TWEET_BUTTON_XPATH, behavior, log, stop).await?;
 
-        let elem_btn = driver
-            .find_element(By::XPath("//*[@data-testid = \"tweetButtonInline\"]"))
-            .await?;
-
-        elem_btn.click().await?;
+        click(&elem_btn, "tweetButtonInline", behavior, log, stop).await?;
 
         Ok(())
     }
@@ -219,34 +386,26 @@ impl Action {
         driver: &WebDriver,
         object: ImagePost,
         behavior: &Behavior,
+        log: &mut StepLog,
+        stop: &JobControl,
     ) -> WebDriverResult<()> {
-        let elem_input = driver
-            .find_element(By::XPath("//input[@data-testid = \"fileInput\"]"))
-            .await?;
-        elem_input.send_keys(object.path.as_str()).await?;
+        let elem_input = find_xpath(driver, FILE_INPUT_XPATH, behavior, log, stop).await?;
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+        upload(&elem_input, object.path.as_str(), log, stop).await?;
 
-        if let Some(text) = object.text {
-            let elem_ta = driver
-                .find_element(By::XPath("//*[@data-testid = \"tweetTextarea_0\"]"))
-                .await?;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
-            let chars = text.chars();
+        if let Some(text) = object.text {
+            let elem_ta = find_xpath(driver, TEXTAREA_XPATH, behavior, log, stop).await?;
 
-            for char in chars {
-                elem_ta.send_keys(char).await?;
-                sleep(Duration::from_millis(100)).await;
-            }
+            type_text(&elem_ta, text.as_str(), log, stop).await?;
 
-            sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+            stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
         }
 
-        let elem_btn = driver
-            .find_element(By::XPath("//*[@data-testid = \"tweetButtonInline\"]"))
-            .await?;
+        let elem_btn = find_xpath(driver, TWEET_BUTTON_XPATH, behavior, log, stop).await?;
 
-        elem_btn.click().await?;
+        click(&elem_btn, "tweetButtonInline", behavior, log, stop).await?;
 
         Ok(())
     }
@@ -256,42 +415,31 @@ impl Action {
         driver: &WebDriver,
         object: PostRetweetLike,
         behavior: &Behavior,
+        log: &mut StepLog,
+        stop: &JobControl,
     ) -> WebDriverResult<()> {
-        driver.get(object.url).await?;
+        open_url(driver, object.url, OpKind::Engage, behavior, log, stop).await?;
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
-        let elem_rt: WebElement;
+        let rt_xpath = object.number.xpath("retweet");
+        let elem_rt = find_xpath(driver, rt_xpath.as_str(), behavior, log, stop).await?;
 
-        match object.number {
-            PostNumber::First => {
-                elem_rt = driver
-                    .find_element(By::XPath("//*[@data-testid = \"retweet\"]"))
-                    .await?;
-            }
-            PostNumber::Last => {
-                elem_rt = driver
-                    .find_element(By::XPath("//*[@data-testid = \"retweet\"][last()]"))
-                    .await?;
-            }
-            PostNumber::Nth(num) => {
-                elem_rt = driver
-                    .find_element(By::XPath(
-                        format!("(//*[@data-testid = \"retweet\"])[{}]", num).as_str(),
-                    ))
-                    .await?;
-            }
-        }
+        click(&elem_rt, "retweet", behavior, log, stop).await?;
 
-        elem_rt.click().await?;
+        // The confirm menu only opens once the retweet button was really clicked.
+        if log.is_dry_run() {
+            log.skip(StepKind::Resolve, RETWEET_CONFIRM_XPATH, None);
+            log.skip(StepKind::Click, "retweetConfirm", None);
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+            return Ok(());
+        }
 
-        let elem_rt_confirm = driver
-            .find_element(By::XPath("//*[@data-testid = \"retweetConfirm\"]"))
-            .await?;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
+
+        let elem_rt_confirm = find_xpath(driver, RETWEET_CONFIRM_XPATH, behavior, log, stop).await?;
 
-        elem_rt_confirm.click().await?;
+        click(&elem_rt_confirm, "retweetConfirm", behavior, log, stop).await?;
 
         Ok(())
     }
@@ -301,63 +449,51 @@ impl Action {
         driver: &WebDriver,
         object: RtQuotePost,
         behavior: &Behavior,
+        log: &mut StepLog,
+        stop: &JobControl,
     ) -> WebDriverResult<()> {
-        driver.get(object.url).await?;
+        open_url(driver, object.url, OpKind::Post, behavior, log, stop).await?;
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
-        let elem_rt: WebElement;
+        let rt_xpath = object.number.xpath("retweet");
+        let elem_rt = find_xpath(driver, rt_xpath.as_str(), behavior, log, stop).await?;
 
-        match object.number {
-            PostNumber::First => {
-                elem_rt = driver
-                    .find_element(By::XPath("//*[@data-testid = \"retweet\"]"))
-                    .await?;
-            }
-            PostNumber::Last => {
-                elem_rt = driver
-                    .find_element(By::XPath("//*[@data-testid = \"retweet\"][last()]"))
-                    .await?;
-            }
-            PostNumber::Nth(num) => {
-                elem_rt = driver
-                    .find_element(By::XPath(
-                        format!("(//*[@data-testid = \"retweet\"])[{}]", num).as_str(),
-                    ))
-                    .await?;
+        click(&elem_rt, "retweet", behavior, log, stop).await?;
+
+        // Likewise the quote composer, which opens from the retweet menu.
+        if log.is_dry_run() {
+            log.skip(StepKind::Resolve, COMPOSE_LINK_XPATH, None);
+            log.skip(StepKind::Click, "compose/tweet", None);
+
+            if let Some(text) = object.text.as_deref() {
+                log.skip(StepKind::Resolve, LAST_TEXTAREA_XPATH, None);
+                log.skip(StepKind::Type, "tweetTextarea", Some(text));
             }
-        }
 
-        elem_rt.click().await?;
+            log.skip(StepKind::Resolve, TWEET_BUTTON_XPATH, None);
+            log.skip(StepKind::Click, "tweetButtonInline", None);
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+            return Ok(());
+        }
 
-        let elem_rt_confirm = driver
-            .find_element(By::XPath("//a[@href = \"/compose/tweet/\"][last()]"))
-            .await?;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
-        elem_rt_confirm.click().await?;
+        let elem_rt_confirm = find_xpath(driver, COMPOSE_LINK_XPATH, behavior, log, stop).await?;
+
+        click(&elem_rt_confirm, "compose/tweet", behavior, log, stop).await?;
 
         if let Some(text) = object.text {
-            let elem_rt_ta = driver
-                .find_element(By::XPath("//*[@data-testid = \"tweetTextarea_0\"][last()]"))
-                .await?;
+            let elem_rt_ta = find_xpath(driver, LAST_TEXTAREA_XPATH, behavior, log, stop).await?;
 
-            let chars = text.chars();
+            type_text(&elem_rt_ta, text.as_str(), log, stop).await?;
 
-            for char in chars {
-                elem_rt_ta.send_keys(char).await?;
-                sleep(Duration::from_millis(rand_num_wait().into())).await;
-            }
-
-            sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+            stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
         }
 
-        let elem_btn = driver
-            .find_element(By::XPath("//*[@data-testid = \"tweetButtonInline\"]"))
-            .await?;
+        let elem_btn = find_xpath(driver, TWEET_BUTTON_XPATH, behavior, log, stop).await?;
 
-        elem_btn.click().await?;
+        click(&elem_btn, "tweetButtonInline", behavior, log, stop).await?;
 
         Ok(())
     }
@@ -367,34 +503,38 @@ impl Action {
         driver: &WebDriver,
         object: PostRetweetLike,
         behavior: &Behavior,
+        log: &mut StepLog,
+        stop: &JobControl,
     ) -> WebDriverResult<()> {
-        driver.get(object.url).await?;
+        open_url(driver, object.url, OpKind::Engage, behavior, log, stop).await?;
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
-        let elem_like: WebElement;
+        let like_xpath = object.number.xpath("like");
+        let elem_like = find_xpath(driver, like_xpath.as_str(), behavior, log, stop).await?;
 
-        match object.number {
-            PostNumber::First => {
-                elem_like = driver
-                    .find_element(By::XPath("//*[@data-testid = \"like\"]"))
-                    .await?;
-            }
-            PostNumber::Last => {
-                elem_like = driver
-                    .find_element(By::XPath("//*[@data-testid = \"like\"][last()]"))
-                    .await?;
-            }
-            PostNumber::Nth(num) => {
-                elem_like = driver
-                    .find_element(By::XPath(
-                        format!("(//*[@data-testid = \"like\"])[{}]", num).as_str(),
-                    ))
-                    .await?;
-            }
-        }
+        click(&elem_like, "like", behavior, log, stop).await?;
 
-        elem_like.click().await?;
+        Ok(())
+    }
+
+    pub async fn capture_post(
+        &self,
+        driver: &WebDriver,
+        object: Capture,
+        behavior: &Behavior,
+        log: &mut StepLog,
+        stop: &JobControl,
+    ) -> WebDriverResult<()> {
+        let url = object.post().url();
+
+        open_url(driver, url.clone(), OpKind::Record, behavior, log, stop).await?;
+
+        find_xpath(driver, ARTICLE_XPATH, behavior, log, stop).await?;
+
+        if log.record(StepKind::Capture, url.as_str(), None) {
+            stop.guard(object.save(driver)).await?;
+        }
 
         Ok(())
     }
@@ -404,29 +544,22 @@ impl Action {
         driver: &WebDriver,
         object: TextComment,
         behavior: &Behavior,
+        log: &mut StepLog,
+        stop: &JobControl,
     ) -> WebDriverResult<()> {
-        driver.get(object.url).await?;
+        open_url(driver, object.url, OpKind::Post, behavior, log, stop).await?;
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
-        let elem_ta = driver
-            .find_element(By::XPath("//*[@data-testid = \"tweetTextarea_0\"][last()]"))
-            .await?;
+        let elem_ta = find_xpath(driver, LAST_TEXTAREA_XPATH, behavior, log, stop).await?;
 
-        let chars = object.text.chars();
+        type_text(&elem_ta, object.text.as_str(), log, stop).await?;
 
-        for char in chars {
-            elem_ta.send_keys(char).await?;
-            sleep(Duration::from_millis(rand_num_wait().into())).await;
-        }
-
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
-        let elem_btn = driver
-            .find_element(By::XPath("//*[@data-testid = \"tweetButtonInline\"]"))
-            .await?;
+        let elem_btn = find_xpath(driver, TWEET_BUTTON_XPATH, behavior, log, stop).await?;
 
-        elem_btn.click().await?;
+        click(&elem_btn, "tweetButtonInline", behavior, log, stop).await?;
 
         Ok(())
     }
@@ -436,38 +569,30 @@ impl Action {
         driver: &WebDriver,
         object: ImageComment,
         behavior: &Behavior,
+        log: &mut StepLog,
+        stop: &JobControl,
     ) -> WebDriverResult<()> {
-        driver.get(object.url).await?;
+        open_url(driver, object.url, OpKind::Post, behavior, log, stop).await?;
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
-        let elem_input = driver
-            .find_element(By::XPath("//input[@data-testid = \"fileInput\"]"))
-            .await?;
-        elem_input.send_keys(object.path.as_str()).await?;
+        let elem_input = find_xpath(driver, FILE_INPUT_XPATH, behavior, log, stop).await?;
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+        upload(&elem_input, object.path.as_str(), log, stop).await?;
 
-        if let Some(text) = object.text {
-            let elem_ta = driver
-                .find_element(By::XPath("//*[@data-testid = \"tweetTextarea_0\"]"))
-                .await?;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
-            let chars = text.chars();
+        if let Some(text) = object.text {
+            let elem_ta = find_xpath(driver, TEXTAREA_XPATH, behavior, log, stop).await?;
 
-            for char in chars {
-                elem_ta.send_keys(char).await?;
-                sleep(Duration::from_millis(100)).await;
-            }
+            type_text(&elem_ta, text.as_str(), log, stop).await?;
 
-            sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+            stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
         }
 
-        let elem_btn = driver
-            .find_element(By::XPath("//*[@data-testid = \"tweetButtonInline\"]"))
-            .await?;
+        let elem_btn = find_xpath(driver, TWEET_BUTTON_XPATH, behavior, log, stop).await?;
 
-        elem_btn.click().await?;
+        click(&elem_btn, "tweetButtonInline", behavior, log, stop).await?;
 
         Ok(())
     }
@@ -477,16 +602,24 @@ impl Action {
         driver: &WebDriver,
         object: Search,
         behavior: &Behavior,
+        log: &mut StepLog,
+        stop: &JobControl,
     ) -> WebDriverResult<Vec<String>> {
         let url = object.format_url();
 
-        driver.get(url).await?;
+        open_url(driver, url.clone(), OpKind::Search, behavior, log, stop).await?;
 
-        sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await;
+        stop.sleep(Duration::from_millis(behavior.run_erratic_wait().into())).await?;
 
         let mut hrefs = HashSet::<String>::new();
 
+        if !log.record(StepKind::Search, url.as_str(), None) {
+            return Ok(vec![]);
+        }
+
         loop {
+            stop.checkpoint().await?;
+
             driver
             .execute_script(
                 r#"
@@ -524,7 +657,7 @@ impl Action {
             )
             .await?;
 
-            sleep(Duration::from_millis(1000)).await;
+            stop.sleep(Duration::from_millis(1000)).await?;
 
             driver
                 .find_elements(By::XPath("//a[contains(@id, 'hrefStatus')]"))
@@ -546,3 +679,86 @@ impl Action {
         Ok(urls)
     }
 }
+
+async fn open_url(
+    driver: &WebDriver,
+    url: String,
+    op: OpKind,
+    behavior: &Behavior,
+    log: &mut StepLog,
+    stop: &JobControl,
+) -> WebDriverResult<()> {
+    log.record(StepKind::Navigate, url.as_str(), None);
+
+    stop.guard(with_retry(&behavior.retry, url.as_str(), || {
+        limited_get(driver, url.clone(), op.clone())
+    }))
+    .await?;
+
+    watch_console(driver).await;
+
+    Ok(())
+}
+
+async fn find_xpath<'a>(
+    driver: &'a WebDriver,
+    xpath: &str,
+    behavior: &Behavior,
+    log: &mut StepLog,
+    stop: &JobControl,
+) -> WebDriverResult<WebElement<'a>> {
+    log.record(StepKind::Resolve, xpath, None);
+
+    stop.guard(with_retry(&behavior.retry, xpath, || driver.find_element(By::XPath(xpath))))
+        .await
+}
+
+async fn click(
+    elem: &WebElement<'_>,
+    step: &str,
+    behavior: &Behavior,
+    log: &mut StepLog,
+    stop: &JobControl,
+) -> WebDriverResult<()> {
+    if !log.record(StepKind::Click, step, None) {
+        return Ok(());
+    }
+
+    // Posting, retweeting and liking are not idempotent, so a click is only tried again
+    // when it cannot have reached the page.
+    stop.guard(with_retry_if(&behavior.retry, step, click_not_delivered, || elem.click()))
+        .await
+}
+
+async fn type_text(
+    elem: &WebElement<'_>,
+    text: &str,
+    log: &mut StepLog,
+    stop: &JobControl,
+) -> WebDriverResult<()> {
+    if !log.record(StepKind::Type, "tweetTextarea", Some(text)) {
+        return Ok(());
+    }
+
+    for char in text.chars() {
+        stop.guard(elem.send_keys(char)).await?;
+        stop.sleep(Duration::from_millis(rand_num_wait().into())).await?;
+    }
+
+    Ok(())
+}
+
+async fn upload(
+    elem: &WebElement<'_>,
+    path: &str,
+    log: &mut StepLog,
+    stop: &JobControl,
+) -> WebDriverResult<()> {
+    if !log.record(StepKind::Upload, "fileInput", Some(path)) {
+        return Ok(());
+    }
+
+    stop.guard(elem.send_keys(path)).await?;
+
+    Ok(())
+}
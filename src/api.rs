@@ -0,0 +1,550 @@
+use crate::action::Action;
+use crate::approval::{ApprovalQueue, ApprovalStatus, PendingAction};
+use crate::audit::{AuditEntry, AuditLog, AuditOutcome};
+use crate::cronueue::CronueueAction;
+use crate::dry_run::ExecMode;
+use crate::jobs::{JobStatus, JobStore, StoredJob};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header::AUTHORIZATION, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+
+const DEFAULT_RUNS: i64 = 20;
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct ApiConfig {
+    /// Address to listen on. Anything but loopback exposes the bot to the network.
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    /// Environment variable holding the bearer token, so the token stays out of the config.
+    pub token_env: String,
+}
+
+fn default_bind() -> String {
+    String::from("127.0.0.1:8787")
+}
+
+#[derive(Clone)]
+struct ApiState {
+    db: Database,
+    token: Arc<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ApiErrorBody {
+    pub error: String,
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(ApiErrorBody { error: self.1 })).into_response()
+    }
+}
+
+fn internal(e: impl std::fmt::Display) -> ApiError {
+    ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn not_found(what: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("no {}", what))
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct JobSummary {
+    pub name: String,
+    pub status: JobStatus,
+    /// Which `Action` variant the job runs.
+    pub action: String,
+    pub dry_run: bool,
+    pub times_ran: u32,
+    pub last_run_at: Option<DateTime<Utc>>,
+    /// Empty once the job has nothing left to run.
+    pub next_fire: Option<DateTime<Utc>>,
+    /// The jobs file the job comes from. Changes made here are undone when it is edited.
+    pub source: Option<String>,
+}
+
+impl From<&StoredJob> for JobSummary {
+    fn from(job: &StoredJob) -> Self {
+        JobSummary {
+            name: job.name.clone(),
+            status: job.status,
+            action: job.job.action().kind().to_string(),
+            dry_run: matches!(job.job.mode(), ExecMode::DryRun { .. }),
+            times_ran: job.times_ran,
+            last_run_at: job.last_run_at,
+            next_fire: job.next_fire(),
+            source: job.source.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct CreateJob {
+    pub name: String,
+    /// A `CronueueAction`, in the same JSON the `jobs add` command reads.
+    #[schema(value_type = Object)]
+    pub job: CronueueAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct JobRun {
+    pub action: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub outcome: AuditOutcome,
+    pub artifacts: Option<String>,
+}
+
+impl From<AuditEntry> for JobRun {
+    fn from(entry: AuditEntry) -> Self {
+        JobRun {
+            action: entry.action,
+            started_at: entry.started_at,
+            finished_at: entry.finished_at,
+            outcome: entry.outcome,
+            artifacts: entry.artifacts,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RunsQuery {
+    /// How many runs to return, newest first. Defaults to 20.
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct PendingSummary {
+    pub id: String,
+    pub job: String,
+    pub action: String,
+    /// What the action would do, as shown to approvers.
+    pub rendered: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<PendingAction> for PendingSummary {
+    fn from(pending: PendingAction) -> Self {
+        PendingSummary {
+            id: pending.id,
+            job: pending.job,
+            action: pending.action.kind().to_string(),
+            rendered: pending.rendered,
+            created_at: pending.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ApproveRequest {
+    /// Who signed the action off, for the decision log.
+    pub by: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct RejectRequest {
+    pub by: String,
+    /// Why the action may not run, kept with the decision.
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct EditRequest {
+    pub by: String,
+    /// The replacement, an `Action` of the same variant. It still needs approving.
+    #[schema(value_type = Object)]
+    pub action: Action,
+}
+
+struct TokenAuth;
+
+impl Modify for TokenAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "token",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+        }
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        list_jobs,
+        create_job,
+        pause_job,
+        resume_job,
+        terminate_job,
+        job_runs,
+        list_approvals,
+        approve_action,
+        reject_action,
+        edit_action
+    ),
+    components(schemas(
+        JobSummary,
+        JobStatus,
+        CreateJob,
+        JobRun,
+        AuditOutcome,
+        PendingSummary,
+        ApproveRequest,
+        RejectRequest,
+        EditRequest,
+        ApiErrorBody
+    )),
+    modifiers(&TokenAuth),
+    security(("token" = []))
+)]
+pub struct ApiDoc;
+
+pub fn openapi_json() -> String {
+    ApiDoc::openapi().to_pretty_json().unwrap()
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    responses((status = 200, description = "Every job in the job store", body = [JobSummary]))
+)]
+async fn list_jobs(State(state): State<ApiState>) -> Result<Json<Vec<JobSummary>>, ApiError> {
+    let jobs = JobStore::new(&state.db).list().await.map_err(internal)?;
+
+    Ok(Json(jobs.iter().map(JobSummary::from).collect()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/jobs",
+    request_body = CreateJob,
+    responses(
+        (status = 201, description = "Job added, the daemon picks it up", body = JobSummary),
+        (status = 409, description = "A job with this name exists", body = ApiErrorBody)
+    )
+)]
+async fn create_job(
+    State(state): State<ApiState>,
+    Json(body): Json<CreateJob>,
+) -> Result<(StatusCode, Json<JobSummary>), ApiError> {
+    let store = JobStore::new(&state.db);
+
+    if store.get(body.name.as_str()).await.map_err(internal)?.is_some() {
+        let message = format!("job {} already exists", body.name);
+
+        return Err(ApiError(StatusCode::CONFLICT, message));
+    }
+
+    let stored = store.add(body.name.as_str(), body.job).await.map_err(internal)?;
+
+    Ok((StatusCode::CREATED, Json(JobSummary::from(&stored))))
+}
+
+async fn set_status(
+    state: &ApiState,
+    name: &str,
+    status: JobStatus,
+) -> Result<JobSummary, ApiError> {
+    let store = JobStore::new(&state.db);
+
+    if store.get(name).await.map_err(internal)?.is_none() {
+        return Err(not_found(format!("job {}", name).as_str()));
+    }
+
+    store.set_status(name, status).await.map_err(internal)?;
+
+    match store.get(name).await.map_err(internal)? {
+        Some(job) => Ok(JobSummary::from(&job)),
+        None => Err(not_found(format!("job {}", name).as_str())),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/jobs/{name}/pause",
+    params(("name" = String, Path, description = "Job name")),
+    responses(
+        (status = 200, description = "Job paused", body = JobSummary),
+        (status = 404, description = "No such job", body = ApiErrorBody)
+    )
+)]
+async fn pause_job(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> Result<Json<JobSummary>, ApiError> {
+    Ok(Json(set_status(&state, name.as_str(), JobStatus::Paused).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/jobs/{name}/resume",
+    params(("name" = String, Path, description = "Job name")),
+    responses(
+        (status = 200, description = "Job resumed", body = JobSummary),
+        (status = 404, description = "No such job", body = ApiErrorBody)
+    )
+)]
+async fn resume_job(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> Result<Json<JobSummary>, ApiError> {
+    Ok(Json(set_status(&state, name.as_str(), JobStatus::Active).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/jobs/{name}",
+    params(("name" = String, Path, description = "Job name")),
+    responses(
+        (status = 204, description = "Job removed, the daemon stops it"),
+        (status = 404, description = "No such job", body = ApiErrorBody)
+    )
+)]
+async fn terminate_job(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let store = JobStore::new(&state.db);
+
+    if store.get(name.as_str()).await.map_err(internal)?.is_none() {
+        return Err(not_found(format!("job {}", name).as_str()));
+    }
+
+    store.remove(name.as_str()).await.map_err(internal)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{name}/runs",
+    params(("name" = String, Path, description = "Job name"), RunsQuery),
+    responses((status = 200, description = "Runs of the job, newest first", body = [JobRun]))
+)]
+async fn job_runs(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+    Query(query): Query<RunsQuery>,
+) -> Result<Json<Vec<JobRun>>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_RUNS).max(1);
+
+    let entries = AuditLog::new(&state.db)
+        .job_history(name.as_str(), limit)
+        .await
+        .map_err(internal)?;
+
+    Ok(Json(entries.into_iter().map(JobRun::from).collect()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/approvals",
+    responses((status = 200, description = "Actions waiting for sign-off", body = [PendingSummary]))
+)]
+async fn list_approvals(
+    State(state): State<ApiState>,
+) -> Result<Json<Vec<PendingSummary>>, ApiError> {
+    let pending = ApprovalQueue::new(&state.db).list_pending().await.map_err(internal)?;
+
+    Ok(Json(pending.into_iter().map(PendingSummary::from).collect()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/approvals/{id}/approve",
+    params(("id" = String, Path, description = "Pending action id")),
+    request_body = ApproveRequest,
+    responses(
+        (status = 200, description = "Approved, the job runs it next", body = PendingSummary),
+        (status = 404, description = "No such pending action", body = ApiErrorBody),
+        (status = 409, description = "Already decided", body = ApiErrorBody)
+    )
+)]
+async fn approve_action(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    Json(body): Json<ApproveRequest>,
+) -> Result<Json<PendingSummary>, ApiError> {
+    let queue = ApprovalQueue::new(&state.db);
+
+    still_pending(&queue, id.as_str()).await?;
+
+    let approved = queue.approve(id.as_str(), body.by.as_str()).await.map_err(internal)?;
+
+    Ok(Json(PendingSummary::from(approved)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/approvals/{id}/reject",
+    params(("id" = String, Path, description = "Pending action id")),
+    request_body = RejectRequest,
+    responses(
+        (status = 200, description = "Rejected, the job skips this run", body = PendingSummary),
+        (status = 404, description = "No such pending action", body = ApiErrorBody),
+        (status = 409, description = "Already decided", body = ApiErrorBody)
+    )
+)]
+async fn reject_action(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    Json(body): Json<RejectRequest>,
+) -> Result<Json<PendingSummary>, ApiError> {
+    let queue = ApprovalQueue::new(&state.db);
+
+    still_pending(&queue, id.as_str()).await?;
+
+    let rejected = queue
+        .reject(id.as_str(), body.by.as_str(), body.reason)
+        .await
+        .map_err(internal)?;
+
+    Ok(Json(PendingSummary::from(rejected)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/approvals/{id}/edit",
+    params(("id" = String, Path, description = "Pending action id")),
+    request_body = EditRequest,
+    responses(
+        (status = 200, description = "Edited, still waiting for approval", body = PendingSummary),
+        (status = 400, description = "The edit changes the action variant", body = ApiErrorBody),
+        (status = 404, description = "No such pending action", body = ApiErrorBody),
+        (status = 409, description = "Already decided", body = ApiErrorBody)
+    )
+)]
+async fn edit_action(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    Json(body): Json<EditRequest>,
+) -> Result<Json<PendingSummary>, ApiError> {
+    let queue = ApprovalQueue::new(&state.db);
+
+    let pending = still_pending(&queue, id.as_str()).await?;
+
+    if pending.action.kind() != body.action.kind() {
+        let (from, to) = (pending.action.kind(), body.action.kind());
+        let message = format!("cannot edit a {} into a {}", from, to);
+
+        return Err(ApiError(StatusCode::BAD_REQUEST, message));
+    }
+
+    let edited = queue
+        .edit(id.as_str(), body.by.as_str(), body.action)
+        .await
+        .map_err(internal)?;
+
+    Ok(Json(PendingSummary::from(edited)))
+}
+
+/// The pending action `id`, as long as nobody decided on it yet.
+async fn still_pending(queue: &ApprovalQueue, id: &str) -> Result<PendingAction, ApiError> {
+    let pending = match queue.get(id).await.map_err(internal)? {
+        Some(pending) => pending,
+        None => return Err(not_found(format!("pending action {}", id).as_str())),
+    };
+
+    if pending.status != ApprovalStatus::Pending {
+        let message = format!("action {} is {:?}", id, pending.status);
+
+        return Err(ApiError(StatusCode::CONFLICT, message));
+    }
+
+    Ok(pending)
+}
+
+async fn openapi() -> impl IntoResponse {
+    ([("content-type", "application/json")], openapi_json())
+}
+
+async fn require_token(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    let given = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
+
+    match given {
+        Some(given) if same_token(given, state.token.as_str()) => next.run(request).await,
+        _ => ApiError(StatusCode::UNAUTHORIZED, String::from("missing or wrong token"))
+            .into_response(),
+    }
+}
+
+/// Compares without stopping at the first differing byte.
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Every route but `/openapi.json` needs `Authorization: Bearer <token>`.
+pub(crate) fn router(db: Database, token: &str) -> Router {
+    let state = ApiState {
+        db,
+        token: Arc::new(token.to_string()),
+    };
+
+    Router::new()
+        .route("/jobs", get(list_jobs).post(create_job))
+        .route("/jobs/:name", axum::routing::delete(terminate_job))
+        .route("/jobs/:name/pause", post(pause_job))
+        .route("/jobs/:name/resume", post(resume_job))
+        .route("/jobs/:name/runs", get(job_runs))
+        .route("/approvals", get(list_approvals))
+        .route("/approvals/:id/approve", post(approve_action))
+        .route("/approvals/:id/reject", post(reject_action))
+        .route("/approvals/:id/edit", post(edit_action))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .route("/openapi.json", get(openapi))
+        .with_state(state)
+}
+
+/// Serves the control API until `stop` is cancelled. Jobs are changed in the job store,
+/// and the daemon applies the changes on its next sync.
+pub async fn serve(
+    db: Database,
+    config: ApiConfig,
+    stop: CancellationToken,
+) -> std::io::Result<()> {
+    let token = std::env::var(config.token_env.as_str()).unwrap_or_default();
+
+    if token.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("no API token in ${}", config.token_env),
+        ));
+    }
+
+    let addr: SocketAddr = config
+        .bind
+        .parse()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    if !addr.ip().is_loopback() {
+        tracing::warn!(bind = config.bind.as_str(), "control API is reachable from the network");
+    }
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+
+    tracing::info!(bind = config.bind.as_str(), "control API listening");
+
+    axum::serve(listener, router(db, token.as_str()))
+        .with_graceful_shutdown(async move { stop.cancelled().await })
+        .await
+}
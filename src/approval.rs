@@ -0,0 +1,271 @@
+use crate::action::Action;
+use crate::audit::AuditLog;
+use crate::content_policy::ContentReview;
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, to_bson};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::result::Result;
+
+const PENDING_COLL_NAME: &str = "pending-approvals";
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum ApprovalStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Executed,
+    /// Nobody decided before the approval timeout, so the run was skipped.
+    Expired,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct PendingAction {
+    pub id: String,
+    pub job: String,
+    #[serde(default)]
+    pub account: String,
+    pub action: Action,
+    pub rendered: String,
+    pub review: Option<ContentReview>,
+    pub status: ApprovalStatus,
+    pub created_at: DateTime<Utc>,
+    pub decided_at: Option<DateTime<Utc>>,
+    pub decided_by: Option<String>,
+    pub note: Option<String>,
+}
+
+impl PendingAction {
+    pub fn expires_at(&self, timeout: Duration) -> DateTime<Utc> {
+        self.created_at + timeout
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Approve,
+    Reject,
+    Edit,
+    Expire,
+}
+
+impl Decision {
+    /// The status a pending action is left in.
+    pub fn status(&self) -> ApprovalStatus {
+        match self {
+            Decision::Approve => ApprovalStatus::Approved,
+            Decision::Reject => ApprovalStatus::Rejected,
+            Decision::Edit => ApprovalStatus::Pending,
+            Decision::Expire => ApprovalStatus::Expired,
+        }
+    }
+}
+
+/// Kept in the audit log, in the entry for the decision.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct ApprovalDecision {
+    pub pending_id: String,
+    pub decision: Decision,
+    pub by: String,
+    pub note: Option<String>,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug)]
+pub struct ApprovalError {
+    details: String,
+}
+
+impl fmt::Display for ApprovalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for ApprovalError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl ApprovalError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = ApprovalError {
+            details: details.to_string(),
+        };
+
+        Box::from(err)
+    }
+}
+
+impl From<mongodb::error::Error> for Box<ApprovalError> {
+    fn from(e: mongodb::error::Error) -> Self {
+        ApprovalError::new(e.to_string().as_str())
+    }
+}
+
+pub struct ApprovalQueue {
+    pending: Collection<PendingAction>,
+    audit: AuditLog,
+}
+
+impl ApprovalQueue {
+    pub fn new(db: &Database) -> Self {
+        ApprovalQueue {
+            pending: db.collection::<PendingAction>(PENDING_COLL_NAME),
+            audit: AuditLog::new(db),
+        }
+    }
+
+    pub async fn submit(
+        &self,
+        job: &str,
+        account: &str,
+        action: &Action,
+        review: Option<ContentReview>,
+    ) -> Result<String, Box<ApprovalError>> {
+        let pending = PendingAction {
+            id: ObjectId::new().to_hex(),
+            job: job.to_string(),
+            account: account.to_string(),
+            action: action.clone(),
+            rendered: action.render(),
+            review,
+            status: ApprovalStatus::Pending,
+            created_at: Utc::now(),
+            decided_at: None,
+            decided_by: None,
+            note: None,
+        };
+
+        self.pending.insert_one(&pending, None).await?;
+
+        tracing::info!(id = pending.id.as_str(), job, "action waiting for approval");
+
+        Ok(pending.id)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<PendingAction>, Box<ApprovalError>> {
+        let found = self.pending.find_one(doc! {"id": id}, None).await?;
+
+        Ok(found)
+    }
+
+    pub async fn list_pending(&self) -> Result<Vec<PendingAction>, Box<ApprovalError>> {
+        let cursor = self.pending.find(doc! {"status": "Pending"}, None).await?;
+
+        let pending: Vec<PendingAction> = cursor.try_collect().await?;
+
+        Ok(pending)
+    }
+
+    async fn decide(
+        &self,
+        id: &str,
+        decision: Decision,
+        by: &str,
+        note: Option<String>,
+        action: Option<Action>,
+    ) -> Result<PendingAction, Box<ApprovalError>> {
+        let mut pending = match self.get(id).await? {
+            Some(pending) => pending,
+            None => return Err(ApprovalError::new(format!("no pending action {}", id).as_str())),
+        };
+
+        if pending.status != ApprovalStatus::Pending {
+            return Err(ApprovalError::new(
+                format!("action {} was already {:?}", id, pending.status).as_str(),
+            ));
+        }
+
+        let before = pending.rendered.clone();
+
+        if let Some(action) = action {
+            if action.kind() != pending.action.kind() {
+                return Err(ApprovalError::new(
+                    format!("cannot edit a {} into a {}", pending.action.kind(), action.kind())
+                        .as_str(),
+                ));
+            }
+
+            pending.rendered = action.render();
+            pending.action = action;
+        }
+
+        pending.status = decision.status();
+        pending.decided_at = Some(Utc::now());
+        pending.decided_by = Some(by.to_string());
+        pending.note = note.clone();
+
+        // Only while still pending, so two approvers, or an approver and the timeout,
+        // cannot both decide.
+        let replaced = self
+            .pending
+            .replace_one(doc! {"id": id, "status": "Pending"}, &pending, None)
+            .await?;
+
+        if replaced.matched_count == 0 {
+            return Err(ApprovalError::new(format!("action {} was already decided", id).as_str()));
+        }
+
+        tracing::info!(id, by, decision = ?decision, "approval decision");
+
+        let record = ApprovalDecision {
+            pending_id: id.to_string(),
+            decision,
+            by: by.to_string(),
+            note,
+            before,
+            after: pending.rendered.clone(),
+        };
+
+        self.audit
+            .append_decision(&pending.job, &pending.account, &pending.action, record)
+            .await
+            .map_err(|e| ApprovalError::new(e.to_string().as_str()))?;
+
+        Ok(pending)
+    }
+
+    pub async fn approve(&self, id: &str, by: &str) -> Result<PendingAction, Box<ApprovalError>> {
+        self.decide(id, Decision::Approve, by, None, None).await
+    }
+
+    pub async fn reject(
+        &self,
+        id: &str,
+        by: &str,
+        reason: Option<String>,
+    ) -> Result<PendingAction, Box<ApprovalError>> {
+        self.decide(id, Decision::Reject, by, reason, None).await
+    }
+
+    /// Replaces the stored action; it still needs approving before it runs.
+    pub async fn edit(
+        &self,
+        id: &str,
+        by: &str,
+        action: Action,
+    ) -> Result<PendingAction, Box<ApprovalError>> {
+        self.decide(id, Decision::Edit, by, None, Some(action)).await
+    }
+
+    /// Gives up on an action nobody decided on in time.
+    pub async fn expire(&self, id: &str) -> Result<PendingAction, Box<ApprovalError>> {
+        self.decide(id, Decision::Expire, "approval timeout", None, None).await
+    }
+
+    pub async fn mark_executed(&self, id: &str) -> Result<(), Box<ApprovalError>> {
+        let status = to_bson(&ApprovalStatus::Executed).unwrap();
+
+        self.pending
+            .update_one(doc! {"id": id}, doc! {"$set": {"status": status}}, None)
+            .await?;
+
+        Ok(())
+    }
+}
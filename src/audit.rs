@@ -0,0 +1,327 @@
+use crate::action::Action;
+use crate::approval::ApprovalDecision;
+use crate::record_posts::PostRef;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{FindOneOptions, FindOptions, IndexOptions};
+use mongodb::{Collection, Database, IndexModel};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+use std::result::Result;
+use tokio::sync::Mutex;
+
+const AUDIT_COLL_NAME: &str = "audit-log";
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const DUPLICATE_KEY: i32 = 11000;
+const MAX_APPEND_ATTEMPTS: u32 = 10;
+
+lazy_static! {
+    /// Orders appends within this process. Holds whether the `seq` index was created yet.
+    static ref APPEND_LOCK: Mutex<bool> = Mutex::new(false);
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "http-api", derive(utoipa::ToSchema))]
+pub enum AuditOutcome {
+    Done,
+    DryRun,
+    Failed(String),
+    /// An approval decision rather than a run; the entry's `decision` says which.
+    Decided,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub seq: i64,
+    pub job: String,
+    pub account: String,
+    pub action: String,
+    pub target: Option<PostRef>,
+    pub content_hash: Option<String>,
+    pub disclosure_label: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub started_ms: i64,
+    pub outcome: AuditOutcome,
+    /// Where the failure artifacts of a failed step were saved; left out like `decision`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifacts: Option<String>,
+    /// Set on entries that record an approval decision. Left out of entries that have
+    /// none, so their hashes are the same as before the field existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decision: Option<ApprovalDecision>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    /// Hashes every field except `hash` itself, chained onto the previous entry's hash.
+    pub(crate) fn compute_hash(&self) -> String {
+        let mut unhashed = self.clone();
+        unhashed.hash = String::new();
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.prev_hash.as_bytes());
+        hasher.update(serde_json::to_string(&unhashed).unwrap().as_bytes());
+
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Checks entries one by one, oldest first, against the chain so far.
+pub(crate) struct ChainCheck {
+    next_seq: i64,
+    prev_hash: String,
+}
+
+impl ChainCheck {
+    pub fn new() -> Self {
+        ChainCheck {
+            next_seq: 0,
+            prev_hash: GENESIS_HASH.to_string(),
+        }
+    }
+
+    /// The seq the next entry must have, which is also where a broken chain broke.
+    pub fn next_seq(&self) -> i64 {
+        self.next_seq
+    }
+
+    /// Whether `entry` follows on from the entries before it, unaltered.
+    pub fn push(&mut self, entry: &AuditEntry) -> bool {
+        if entry.seq != self.next_seq
+            || entry.prev_hash != self.prev_hash
+            || entry.hash != entry.compute_hash()
+        {
+            return false;
+        }
+
+        self.prev_hash = entry.hash.clone();
+        self.next_seq += 1;
+
+        true
+    }
+}
+
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    matches!(
+        e.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(x)) if x.code == DUPLICATE_KEY
+    )
+}
+
+#[derive(Debug)]
+pub struct AuditError {
+    details: String,
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for AuditError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl AuditError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = AuditError {
+            details: details.to_string(),
+        };
+
+        Box::from(err)
+    }
+}
+
+impl From<mongodb::error::Error> for Box<AuditError> {
+    fn from(e: mongodb::error::Error) -> Self {
+        AuditError::new(e.to_string().as_str())
+    }
+}
+
+pub struct AuditLog {
+    entries: Collection<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn new(db: &Database) -> Self {
+        AuditLog {
+            entries: db.collection::<AuditEntry>(AUDIT_COLL_NAME),
+        }
+    }
+
+    async fn last_entry(&self) -> Result<Option<AuditEntry>, Box<AuditError>> {
+        let options = FindOneOptions::builder().sort(doc! {"seq": -1}).build();
+
+        let last = self.entries.find_one(None, options).await?;
+
+        Ok(last)
+    }
+
+    /// A unique `seq` makes a second writer, in this process or another, lose the race
+    /// for a position in the chain instead of forking it.
+    async fn ensure_seq_index(&self) -> Result<(), Box<AuditError>> {
+        let index = IndexModel::builder()
+            .keys(doc! {"seq": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+
+        self.entries.create_index(index, None).await?;
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn append(
+        &self,
+        job: &str,
+        account: &str,
+        action: &Action,
+        started_at: DateTime<Utc>,
+        outcome: AuditOutcome,
+        disclosure_label: Option<&str>,
+        artifacts: Option<&str>,
+    ) -> Result<AuditEntry, Box<AuditError>> {
+        let entry = AuditEntry {
+            seq: 0,
+            job: job.to_string(),
+            account: account.to_string(),
+            action: action.kind().to_string(),
+            target: action.target(),
+            content_hash: action.content().map(|x| content_hash(x.as_str())),
+            disclosure_label: disclosure_label.map(|x| x.to_string()),
+            started_at,
+            finished_at: Utc::now(),
+            started_ms: started_at.timestamp_millis(),
+            outcome,
+            artifacts: artifacts.map(|x| x.to_string()),
+            decision: None,
+            prev_hash: String::new(),
+            hash: String::new(),
+        };
+
+        self.chain(entry).await
+    }
+
+    /// Records who approved, rejected or edited a pending `action`, in the same chain as
+    /// the runs.
+    pub async fn append_decision(
+        &self,
+        job: &str,
+        account: &str,
+        action: &Action,
+        decision: ApprovalDecision,
+    ) -> Result<AuditEntry, Box<AuditError>> {
+        let now = Utc::now();
+
+        let entry = AuditEntry {
+            seq: 0,
+            job: job.to_string(),
+            account: account.to_string(),
+            action: action.kind().to_string(),
+            target: action.target(),
+            content_hash: action.content().map(|x| content_hash(x.as_str())),
+            disclosure_label: None,
+            started_at: now,
+            finished_at: now,
+            started_ms: now.timestamp_millis(),
+            outcome: AuditOutcome::Decided,
+            artifacts: None,
+            decision: Some(decision),
+            prev_hash: String::new(),
+            hash: String::new(),
+        };
+
+        self.chain(entry).await
+    }
+
+    /// Gives `entry` the next seq and links it onto the last entry.
+    async fn chain(&self, mut entry: AuditEntry) -> Result<AuditEntry, Box<AuditError>> {
+        let mut indexed = APPEND_LOCK.lock().await;
+
+        if !*indexed {
+            self.ensure_seq_index().await?;
+            *indexed = true;
+        }
+
+        for _ in 0..MAX_APPEND_ATTEMPTS {
+            (entry.seq, entry.prev_hash) = match self.last_entry().await? {
+                Some(last) => (last.seq + 1, last.hash),
+                None => (0, GENESIS_HASH.to_string()),
+            };
+
+            entry.hash = entry.compute_hash();
+
+            match self.entries.insert_one(&entry, None).await {
+                Ok(_) => return Ok(entry),
+                // Another writer took this seq first; chain onto its entry instead.
+                Err(e) if is_duplicate_key(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(AuditError::new("audit log kept changing while appending, entry not written"))
+    }
+
+    /// Everything `account` did between `from` and `to`, oldest first.
+    pub async fn query(
+        &self,
+        account: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<AuditEntry>, Box<AuditError>> {
+        let filter = doc! {
+            "account": account,
+            "started_ms": {"$gte": from.timestamp_millis(), "$lte": to.timestamp_millis()},
+        };
+        let options = FindOptions::builder().sort(doc! {"seq": 1}).build();
+
+        let cursor = self.entries.find(filter, options).await?;
+        let entries: Vec<AuditEntry> = cursor.try_collect().await?;
+
+        Ok(entries)
+    }
+
+    /// The latest `limit` runs of `job`, newest first. Approval decisions are left out.
+    pub async fn job_history(
+        &self,
+        job: &str,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, Box<AuditError>> {
+        let options = FindOptions::builder().sort(doc! {"seq": -1}).limit(limit).build();
+        let filter = doc! {"job": job, "decision": null};
+
+        let cursor = self.entries.find(filter, options).await?;
+        let entries: Vec<AuditEntry> = cursor.try_collect().await?;
+
+        Ok(entries)
+    }
+
+    /// Walks the whole chain and returns the first entry that was altered or removed, if any.
+    pub async fn verify(&self) -> Result<Option<i64>, Box<AuditError>> {
+        let options = FindOptions::builder().sort(doc! {"seq": 1}).build();
+
+        let mut cursor = self.entries.find(None, options).await?;
+        let mut chain = ChainCheck::new();
+
+        while let Some(entry) = cursor.try_next().await? {
+            if !chain.push(&entry) {
+                return Ok(Some(chain.next_seq()));
+            }
+        }
+
+        Ok(None)
+    }
+}
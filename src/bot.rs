@@ -1,25 +1,52 @@
 
-use crate::config::{Config, Behavior};
+use crate::config::Config;
 use crate::proxy::Proxy;
+use chrono::{DateTime, Utc};
 use mongodb::Database;
-use thirtyfour::WebDriver;
-use tokio;
+use thirtyfour::prelude::WebDriverResult;
 use crate::read_write_queue::ReadWriteQueue;
 use tokio::sync::Mutex;
 use crate::action::*;
+use crate::capture::Capture;
 use crate::search::Search;
+use crate::session::SessionManager;
+use crate::thread::{ThreadError, ThreadNode, ThreadStore};
+use crate::approval::{ApprovalError, ApprovalQueue, PendingAction};
+use crate::audit::{AuditEntry, AuditError, AuditLog, AuditOutcome};
+use crate::cancel::{
+    install_signal_handler, kill_switch, trigger_kill_switch, watch_sentinel, JobControl,
+};
+use crate::cronueue::{CronueueAction, ExecType};
+use crate::dry_run::{DryRunReport, ExecMode, StepLog};
+use crate::health::{clear_alert, session_alert, session_alerts, SessionAlert};
+use crate::import::{ImportError, ImportProgress, LinkImport};
+use crate::job_file::{JobFile, JobFileChanges, JobFileError};
+use crate::jobs::{JobError, JobStatus, JobStore};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 pub struct Bot {
     name: String,
     queue: ReadWriteQueue,
-    driver: Mutex<WebDriver>,
-    db: Mutex<Database>,
-    behavior: Mutex<Behavior>,
-
+    session: Arc<Mutex<SessionManager>>,
+    db: Arc<Mutex<Database>>,
+    config: Arc<Mutex<Config>>,
+    /// Stops this bot's jobs. A child of the kill switch, so signals stop them too.
+    stop: CancellationToken,
 }
 
 impl Bot {
     #[tokio::main]
     pub async fn new(name_raw: String, proxy_str: String, config_str: String) -> Self {
+        Self::start(name_raw, proxy_str, config_str).await.unwrap()
+    }
+
+    /// Same as `new`, for callers that already run inside a tokio runtime.
+    pub async fn start(
+        name_raw: String,
+        proxy_str: String,
+        config_str: String,
+    ) -> WebDriverResult<Self> {
         let name_clone = name_raw.clone();
         let mut name = name_clone;
         name.push_str("-bot");
@@ -29,84 +56,70 @@ impl Bot {
 
         let queue = ReadWriteQueue::new();
 
-        let driver_result = proxy
-                 .launch_driver_with_proxy(config.clone()).await.unwrap();
-       
+        let session_result = SessionManager::start(proxy, config.clone()).await?;
 
         let db_result = config.clone().create_db().await;
 
-        let db = Mutex::new(db_result);
-        let driver = Mutex::new(driver_result);
-        let behavior = Mutex::new(config.behavior.clone());
+        let db = Arc::new(Mutex::new(db_result));
+        let session = Arc::new(Mutex::new(session_result));
+        let stop = kill_switch().child_token();
 
+        SessionManager::spawn_cookie_saver(session.clone(), stop.clone());
 
-        Bot {
+        Ok(Bot {
             name,
             queue,
-            driver,
+            session,
             db,
-            behavior,
-        }
+            config: Arc::new(Mutex::new(config)),
+            stop,
+        })
     }
 
     pub fn create_post_action(&self, json: String) -> Action {
         let post_post = TextPost::from_text(json);
 
-        let action = Action::PostText(post_post);
-
-        action
+        Action::PostText(post_post)
     }
 
     pub fn create_image_action(&self, json: String) -> Action {
         let post_post = ImagePost::from_text(json);
 
-        let action = Action::PostImage(post_post);
-
-        action
+        Action::PostImage(post_post)
     }
 
 
     pub fn create_like_action(&self, json: String) -> Action {
         let post_post = PostRetweetLike::from_text(json);
 
-        let action = Action::LikePost(post_post);
-
-        action
+        Action::LikePost(post_post)
     }
 
     pub fn create_search_action(&self, json: String) -> Action {
         let post_post = Search::from_json_string(json);
 
-        let action = Action::SearchTwitter(post_post);
-
-        action
+        Action::SearchTwitter(post_post)
     }
 
 
     pub fn create_rt_action(&self, json: String) -> Action {
         let post_post = PostRetweetLike::from_text(json);
 
-        let action = Action::Retweet(post_post);
-
-        action
+        Action::Retweet(post_post)
     }
 
 
     pub fn create_qrt_action(&self, json: String) -> Action {
         let post_post = RtQuotePost::from_text(json);
 
-        let action = Action::QuoteRetweet(post_post);
-
-        action
+        Action::QuoteRetweet(post_post)
     }
 
 
     pub fn create_ctext_action(&self, json: String) -> Action {
         let post_post = TextComment::from_text(json);
 
-        let action = Action::CommentText(post_post);
-
-        action
+        Action::CommentText(post_post)
     }
 
 
@@ -114,8 +127,302 @@ impl Bot {
     pub fn create_cimage_action(&self, json: String) -> Action {
         let post_post = ImageComment::from_text(json);
 
-        let action = Action::CommentImage(post_post);
+        Action::CommentImage(post_post)
+    }
+
+    pub fn create_capture_action(&self, json: String) -> Action {
+        let capture = Capture::from_json_str(json);
+
+        Action::Capture(capture)
+    }
+
+    pub async fn import_links(&self, json: String) -> Result<ImportProgress, Box<ImportError>> {
+        let import = LinkImport::from_json_str(json)?;
+
+        let db = self.db.lock().await;
+
+        import.run(&db).await
+    }
+
+    pub async fn dry_run(&self, action: Action, report_dir: Option<String>) -> DryRunReport {
+        let mode = ExecMode::DryRun { report_dir };
+        let job = CronueueAction::new(Utc::now(), action.clone(), ExecType::Once)
+            .with_mode(mode.clone());
+
+        let mut session = self.session.lock().await;
+
+        let driver = match session.driver().await {
+            Ok(driver) => driver,
+            Err(e) => return StepLog::new(mode).report(action.kind(), Some(e.to_string())),
+        };
+
+        let config = self.config.lock().await;
+        let db = self.db.lock().await;
+
+        let control = JobControl::child_of(&self.stop);
+
+        let (fired, report) = job
+            .run_action(&self.name, action.clone(), driver, &config, &db, &control)
+            .await;
+
+        // A dry run never stops at the quota, so it always comes back with a report.
+        report.unwrap_or_else(|| {
+            StepLog::new(mode).report(action.kind(), Some(format!("did not run: {:?}", fired)))
+        })
+    }
+
+    /// Runs `action` once right away, with the quota, content policy and approval checks
+    /// of a scheduled run. `None` means it was skipped before it started.
+    pub async fn run_now(&self, action: Action) -> Option<AuditOutcome> {
+        CronueueAction::new(Utc::now(), action, ExecType::Once)
+            .fire(&self.name, &self.session, &self.config, &self.db, &self.control())
+            .await
+            .outcome()
+    }
+
+    /// Makes the running jobs match the job store: new jobs are launched, changed ones
+    /// restarted, paused ones paused, and jobs no longer in the store are stopped.
+    pub async fn sync_jobs(&self) -> Result<(), Box<JobError>> {
+        let stored = {
+            let db = self.db.lock().await;
+
+            JobStore::new(&db).list().await?
+        };
+
+        // Jobs paused for a bad session stay paused until the cookies are re-imported,
+        // whatever the job store says.
+        let account = self.config.lock().await.account.clone();
+        let session_paused = session_alert(account.as_str()).await.is_some();
+
+        for job in stored.iter() {
+            if let Some(running) = self.queue.action_of(job.name.as_str()) {
+                if running != job.job {
+                    tracing::info!(job = job.name.as_str(), "job changed, restarting it");
+
+                    self.queue.remove_name(job.name.clone());
+                }
+            }
+
+            let is_new = !self.queue.contains(job.name.as_str());
+
+            if is_new {
+                if job.next_fire().is_none() {
+                    continue;
+                }
+
+                self.queue.add_new_action(job.name.clone(), job.job.clone(), self.control());
+            }
+
+            // Paused before launching, so a paused job cannot slip in a run.
+            match job.status {
+                JobStatus::Active if session_paused => self.queue.pause_name(job.name.clone()),
+                JobStatus::Active => self.queue.resume_name(job.name.clone()),
+                JobStatus::Paused => self.queue.pause_name(job.name.clone()),
+            }
+
+            if is_new {
+                tracing::info!(job = job.name.as_str(), "launching job");
+
+                self.queue.launch_name(
+                    job.name.clone(),
+                    self.session.clone(),
+                    self.config.clone(),
+                    self.db.clone(),
+                );
+            }
+        }
+
+        for name in self.queue.names() {
+            if !stored.iter().any(|x| x.name == name) {
+                tracing::info!(job = name.as_str(), "job removed, stopping it");
+
+                self.queue.remove_name(name);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads a jobs file into the job store. An invalid file changes nothing.
+    pub async fn apply_job_file(&self, path: &str) -> Result<JobFileChanges, Box<JobFileError>> {
+        let file = JobFile::new(path);
+        let defs = file.load()?;
+
+        let db = self.db.lock().await;
+        let changes = file.apply(&JobStore::new(&db), &defs).await?;
+
+        if !changes.is_empty() {
+            tracing::info!(
+                file = path,
+                added = ?changes.added,
+                changed = ?changes.changed,
+                removed = ?changes.removed,
+                "applied jobs file"
+            );
+        }
+
+        Ok(changes)
+    }
+
+    fn control(&self) -> JobControl {
+        JobControl::child_of(&self.stop)
+    }
+
+    /// Runs the jobs in the job store until the bot is stopped, picking up changes
+    /// every `poll`. The configured jobs file is loaded first and again whenever it is
+    /// modified.
+    pub async fn run_daemon(&self, poll: Duration) {
+        let stop = self.stop.clone();
+        let jobs_file = self.config.lock().await.jobs_file.clone();
+        let mut loaded_at = None;
+
+        while !stop.is_cancelled() {
+            if let Some(path) = jobs_file.as_deref() {
+                let modified = std::fs::metadata(path).and_then(|x| x.modified()).ok();
+
+                if modified.is_some() && modified != loaded_at {
+                    loaded_at = modified;
+
+                    if let Err(e) = self.apply_job_file(path).await {
+                        for problem in e.problems() {
+                            tracing::error!(problem = problem.as_str(), "jobs file not applied");
+                        }
+                    }
+                }
+            }
+
+            if let Err(e) = self.sync_jobs().await {
+                tracing::error!(error = %e, "could not sync jobs");
+            }
+
+            tokio::select! {
+                _ = stop.cancelled() => {}
+                _ = tokio::time::sleep(poll) => {}
+            }
+        }
+    }
+
+    /// The recorded conversation `post_id` is part of, as a reply tree.
+    pub async fn thread(&self, post_id: String) -> Result<Option<ThreadNode>, Box<ThreadError>> {
+        let db = self.db.lock().await;
+
+        ThreadStore::new(&db).tree(post_id.as_str()).await
+    }
+
+    pub async fn pending_approvals(&self) -> Result<Vec<PendingAction>, Box<ApprovalError>> {
+        let db = self.db.lock().await;
+
+        ApprovalQueue::new(&db).list_pending().await
+    }
+
+    pub async fn approve_action(
+        &self,
+        id: String,
+        by: String,
+    ) -> Result<PendingAction, Box<ApprovalError>> {
+        let db = self.db.lock().await;
+
+        ApprovalQueue::new(&db).approve(id.as_str(), by.as_str()).await
+    }
+
+    pub async fn reject_action(
+        &self,
+        id: String,
+        by: String,
+        reason: Option<String>,
+    ) -> Result<PendingAction, Box<ApprovalError>> {
+        let db = self.db.lock().await;
+
+        ApprovalQueue::new(&db).reject(id.as_str(), by.as_str(), reason).await
+    }
+
+    pub async fn edit_action(
+        &self,
+        id: String,
+        by: String,
+        json: String,
+    ) -> Result<PendingAction, Box<ApprovalError>> {
+        let action: Action = serde_json::from_str(json.as_str())
+            .map_err(|e| ApprovalError::new(e.to_string().as_str()))?;
+
+        let db = self.db.lock().await;
+
+        ApprovalQueue::new(&db).edit(id.as_str(), by.as_str(), action).await
+    }
+
+    pub async fn audit_trail(
+        &self,
+        account: String,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<AuditEntry>, Box<AuditError>> {
+        let db = self.db.lock().await;
+
+        AuditLog::new(&db).query(account.as_str(), from, to).await
+    }
+
+    pub async fn verify_audit_trail(&self) -> Result<Option<i64>, Box<AuditError>> {
+        let db = self.db.lock().await;
+
+        AuditLog::new(&db).verify().await
+    }
+
+    pub async fn session_alerts(&self) -> Vec<SessionAlert> {
+        session_alerts().await
+    }
+
+    /// Call once fresh cookies are in; resumes the jobs the expired session paused.
+    pub async fn session_restored(&self, account: String) {
+        if let Some(alert) = clear_alert(account.as_str()).await {
+            for job in alert.paused_jobs {
+                self.queue.resume_name(job);
+            }
+        }
+    }
+
+    /// Stops every job on SIGINT/SIGTERM, or when the configured sentinel file shows up.
+    pub async fn stop_on_signals(&self) {
+        install_signal_handler();
+
+        if let Some(path) = self.config.lock().await.kill_sentinel.clone() {
+            watch_sentinel(path);
+        }
+    }
+
+    /// Serves the control API in the background when the config has an `api` section.
+    /// It works on the job store, so run it next to `run_daemon`.
+    #[cfg(feature = "http-api")]
+    pub async fn start_api(&self) -> bool {
+        let api = match self.config.lock().await.api.clone() {
+            Some(api) => api,
+            None => return false,
+        };
+        let db = self.db.lock().await.clone();
+        let stop = self.stop.clone();
+
+        tokio::task::spawn(async move {
+            if let Err(e) = crate::api::serve(db, api, stop).await {
+                tracing::error!(error = %e, "control API stopped");
+            }
+        });
+
+        true
+    }
+
+    pub fn kill(&self, reason: &str) {
+        trigger_kill_switch(reason);
+    }
+
+    pub async fn save_cookies(&self) -> WebDriverResult<usize> {
+        self.session.lock().await.save_cookies().await
+    }
+
+    /// Stops this bot's jobs and closes its browser. Other bots keep running.
+    pub async fn shutdown(self) -> WebDriverResult<()> {
+        tracing::info!(bot = self.name.as_str(), "shutting down");
+
+        self.stop.cancel();
 
-        action
+        self.session.lock().await.shutdown().await
     }
 }
@@ -0,0 +1,149 @@
+use crate::utils::bot_error;
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use thirtyfour::error::WebDriverError;
+use thirtyfour::prelude::WebDriverResult;
+use tokio::time::{sleep, Duration};
+use tokio_util::sync::CancellationToken;
+
+const PAUSE_POLL: Duration = Duration::from_millis(500);
+const SENTINEL_POLL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref KILL_SWITCH: CancellationToken = CancellationToken::new();
+}
+
+pub fn kill_switch() -> CancellationToken {
+    KILL_SWITCH.clone()
+}
+
+pub fn trigger_kill_switch(reason: &str) {
+    tracing::warn!(reason, "kill switch triggered, stopping all automation");
+
+    KILL_SWITCH.cancel();
+}
+
+/// Trips the kill switch on Ctrl-C, and on SIGTERM where there is one.
+pub fn install_signal_handler() {
+    tokio::spawn(async {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut term = signal(SignalKind::terminate()).unwrap();
+
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => trigger_kill_switch("SIGINT"),
+                _ = term.recv() => trigger_kill_switch("SIGTERM"),
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                trigger_kill_switch("Ctrl-C");
+            }
+        }
+    });
+}
+
+/// Trips the kill switch as soon as a file appears at `path`.
+pub fn watch_sentinel(path: String) {
+    tokio::spawn(async move {
+        while !KILL_SWITCH.is_cancelled() {
+            if Path::new(path.as_str()).exists() {
+                trigger_kill_switch(format!("sentinel file {}", path).as_str());
+
+                break;
+            }
+
+            sleep(SENTINEL_POLL).await;
+        }
+    });
+}
+
+pub fn cancelled_error() -> WebDriverError {
+    bot_error(String::from("action cancelled"))
+}
+
+/// Stop and pause state of one job. Cancelling the kill switch, or the bot the job
+/// belongs to, cancels the job.
+#[derive(Clone, Debug)]
+pub struct JobControl {
+    token: CancellationToken,
+    paused: Arc<AtomicBool>,
+}
+
+impl JobControl {
+    pub fn new() -> Self {
+        Self::child_of(&KILL_SWITCH)
+    }
+
+    pub fn child_of(parent: &CancellationToken) -> Self {
+        JobControl {
+            token: parent.child_token(),
+            paused: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Holds while the job is paused, and fails once it is cancelled.
+    pub async fn checkpoint(&self) -> WebDriverResult<()> {
+        while self.is_paused() && !self.is_cancelled() {
+            sleep(PAUSE_POLL).await;
+        }
+
+        if self.is_cancelled() {
+            return Err(cancelled_error());
+        }
+
+        Ok(())
+    }
+
+    pub async fn sleep(&self, duration: Duration) -> WebDriverResult<()> {
+        tokio::select! {
+            _ = self.token.cancelled() => Err(cancelled_error()),
+            _ = sleep(duration) => self.checkpoint().await,
+        }
+    }
+
+    /// Runs `fut` unless the job is stopped first, in which case `fut` is dropped.
+    pub async fn guard<T, F>(&self, fut: F) -> WebDriverResult<T>
+    where
+        F: Future<Output = WebDriverResult<T>>,
+    {
+        self.checkpoint().await?;
+
+        tokio::select! {
+            _ = self.token.cancelled() => Err(cancelled_error()),
+            ret = fut => ret,
+        }
+    }
+}
+
+impl Default for JobControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
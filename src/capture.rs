@@ -0,0 +1,330 @@
+use crate::rate_limit::{host_of, OpKind, RateLimiter};
+use crate::record_posts::PostRef;
+use crate::utils::{bot_error, write_bytes_atomic};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, Value};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use thirtyfour::error::WebDriverError;
+use thirtyfour::prelude::*;
+use thirtyfour::OptionRect;
+
+const SCREENSHOT_FILE: &str = "screenshot.png";
+const HTML_FILE: &str = "page.html";
+const WARC_FILE: &str = "capture.warc";
+const MANIFEST_FILE: &str = "manifest.json";
+const CHECKSUM_FILE: &str = "manifest.sha256";
+const PAGE_SIZE_SCRIPT: &str = r#"
+    return [document.documentElement.scrollWidth, document.documentElement.scrollHeight];
+"#;
+const PAGE_INFO_SCRIPT: &str = r#"
+    const nav = performance.getEntriesByType("navigation")[0];
+    return {
+        userAgent: navigator.userAgent,
+        referrer: document.referrer,
+        navigation: nav ? nav.toJSON() : null
+    };
+"#;
+/// The browser does not hand out the response it rendered, so the page fetches its own
+/// url again with the same cookies. Only the status and headers of that second fetch
+/// are kept, and they are labelled as a refetch in the WARC file.
+const PAGE_REFETCH_SCRIPT: &str = r#"
+    const done = arguments[arguments.length - 1];
+    fetch(location.href, {credentials: "include", cache: "no-store"})
+        .then((res) => {
+            const headers = [];
+            res.headers.forEach((value, name) => headers.push([name, value]));
+            done({status: res.status, statusText: res.statusText, headers});
+        })
+        .catch((e) => done({error: String(e)}));
+"#;
+const REFETCH_NOTE: &str = "the page fetched its own url again after it was captured; this \
+    is not the response the browser rendered";
+
+/// Saves a post as it was shown: a full-page screenshot, the rendered DOM, and a WARC
+/// file holding both next to the navigation metadata the browser reports.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct Capture {
+    post: PostRef,
+    archive_dir: String,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct CapturedFile {
+    pub name: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct CaptureManifest {
+    pub url: String,
+    pub final_url: String,
+    pub username: String,
+    pub post_id: String,
+    pub captured_at: DateTime<Utc>,
+    pub files: Vec<CapturedFile>,
+}
+
+impl Capture {
+    pub fn from_json_str(json_str: String) -> Self {
+        let ret: Capture = from_str(json_str.as_str()).unwrap();
+
+        ret
+    }
+
+    pub fn post(&self) -> &PostRef {
+        &self.post
+    }
+
+    /// One new directory per capture, so capturing the same post twice keeps both, even
+    /// within the same millisecond.
+    pub(crate) fn create_capture_dir(&self, at: DateTime<Utc>) -> std::io::Result<String> {
+        fs::create_dir_all(self.archive_dir.as_str())?;
+
+        let name = format!(
+            "{}-{}-{}",
+            self.post.username,
+            self.post.post_id,
+            at.format("%Y%m%dT%H%M%S%.3fZ")
+        );
+
+        let mut suffix = 0;
+
+        loop {
+            let dir = match suffix {
+                0 => Path::new(self.archive_dir.as_str()).join(name.as_str()),
+                n => Path::new(self.archive_dir.as_str()).join(format!("{}-{}", name, n)),
+            };
+
+            match fs::create_dir(&dir) {
+                Ok(()) => return Ok(dir.to_string_lossy().to_string()),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => suffix += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Captures the page the driver is on, which should already be the post.
+    pub async fn save(&self, driver: &WebDriver) -> WebDriverResult<CaptureManifest> {
+        let captured_at = Utc::now();
+        let dir = self.create_capture_dir(captured_at).map_err(io_error)?;
+
+        let final_url = driver.current_url().await?.to_string();
+        let html = driver.page_source().await?;
+        let screenshot = full_page_screenshot(driver).await?;
+        let page_info = driver.execute_script(PAGE_INFO_SCRIPT).await?.value().clone();
+
+        let refetch = match page_refetch(driver, final_url.as_str()).await {
+            Ok(ret) => refetch_fields(&ret),
+            Err(e) => {
+                tracing::warn!(error = %e, "could not refetch the page for its headers");
+
+                None
+            }
+        };
+
+        let warc = warc_records(
+            final_url.as_str(),
+            captured_at,
+            &page_info,
+            refetch.as_deref(),
+            html.as_bytes(),
+            &screenshot,
+        );
+
+        let mut files = vec![];
+
+        for (name, content) in [
+            (SCREENSHOT_FILE, screenshot.as_slice()),
+            (HTML_FILE, html.as_bytes()),
+            (WARC_FILE, warc.as_slice()),
+        ] {
+            let path = Path::new(dir.as_str()).join(name);
+
+            write_bytes_atomic(path.to_string_lossy().as_ref(), content).map_err(io_error)?;
+
+            files.push(CapturedFile {
+                name: name.to_string(),
+                sha256: sha256_hex(content),
+                bytes: content.len() as u64,
+            });
+        }
+
+        let manifest = CaptureManifest {
+            url: self.post.url(),
+            final_url,
+            username: self.post.username.clone(),
+            post_id: self.post.post_id.clone(),
+            captured_at,
+            files,
+        };
+
+        write_manifest(dir.as_str(), &manifest).map_err(io_error)?;
+
+        tracing::info!(post = manifest.url.as_str(), dir = dir.as_str(), "captured post");
+
+        Ok(manifest)
+    }
+}
+
+fn io_error(e: std::io::Error) -> WebDriverError {
+    bot_error(e.to_string())
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// `manifest.json`, plus a `manifest.sha256` that `sha256sum -c` can check, which also
+/// covers the manifest itself.
+pub fn write_manifest(dir: &str, manifest: &CaptureManifest) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(manifest)?;
+
+    let mut sums: Vec<String> = manifest
+        .files
+        .iter()
+        .map(|x| format!("{}  {}", x.sha256, x.name))
+        .collect();
+    sums.push(format!("{}  {}", sha256_hex(json.as_bytes()), MANIFEST_FILE));
+
+    for (name, content) in [(MANIFEST_FILE, json), (CHECKSUM_FILE, sums.join("\n") + "\n")] {
+        let path = Path::new(dir).join(name);
+
+        write_bytes_atomic(path.to_string_lossy().as_ref(), content.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Grows the window to the whole document for one screenshot, then puts it back.
+async fn full_page_screenshot(driver: &WebDriver) -> WebDriverResult<Vec<u8>> {
+    let size = driver.execute_script(PAGE_SIZE_SCRIPT).await?.value().clone();
+    let rect = driver.get_window_rect().await?;
+
+    let width = size[0].as_u64().unwrap_or(rect.width as u64).max(rect.width as u64);
+    let height = size[1].as_u64().unwrap_or(rect.height as u64).max(rect.height as u64);
+
+    let full = OptionRect::from(rect.clone()).with_width(width as i32).with_height(height as i32);
+
+    driver.set_window_rect(full).await?;
+
+    let screenshot = driver.screenshot_as_png().await;
+
+    driver.set_window_rect(OptionRect::from(rect)).await?;
+
+    screenshot
+}
+
+/// Runs the refetch script once the rate limiter allows another request to the host.
+async fn page_refetch(driver: &WebDriver, url: &str) -> WebDriverResult<Value> {
+    RateLimiter::acquire(host_of(url).as_str(), OpKind::Record)
+        .await
+        .map_err(|e| bot_error(e.to_string()))?;
+
+    let ret = driver.execute_async_script(PAGE_REFETCH_SCRIPT).await?;
+
+    Ok(ret.value().clone())
+}
+
+/// The refetch's status and headers as `application/warc-fields`, or `None` if the fetch
+/// failed.
+pub fn refetch_fields(fetched: &Value) -> Option<String> {
+    let status = fetched["status"].as_u64()?;
+
+    let mut fields = format!(
+        "note: {}\r\nstatus: {} {}\r\n",
+        REFETCH_NOTE,
+        status,
+        fetched["statusText"].as_str().unwrap_or_default()
+    );
+
+    for header in fetched["headers"].as_array().into_iter().flatten() {
+        if let (Some(name), Some(value)) = (header[0].as_str(), header[1].as_str()) {
+            fields.push_str(format!("header: {}: {}\r\n", name, value).as_str());
+        }
+    }
+
+    Some(fields)
+}
+
+/// A WARC 1.1 file with a warcinfo record, the rendered DOM and screenshot as resources,
+/// and the navigation metadata the browser reports. The browser does not expose the
+/// traffic behind the page, so there are no request or response records; the headers
+/// of a refetch, if there are any, go in a metadata record that says so. Every record
+/// after the DOM is marked as concurrent to it.
+pub fn warc_records(
+    url: &str,
+    at: DateTime<Utc>,
+    page_info: &Value,
+    refetch: Option<&str>,
+    html: &[u8],
+    screenshot: &[u8],
+) -> Vec<u8> {
+    let date = at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+
+    let info = format!("software: {}\r\nformat: WARC File Format 1.1\r\n", env!("CARGO_PKG_NAME"));
+    let metadata = serde_json::to_string(page_info).unwrap_or_default();
+
+    let mut records: Vec<(&str, Option<&str>, &str, &[u8])> = vec![
+        ("warcinfo", None, "application/warc-fields", info.as_bytes()),
+        ("resource", Some(url), "text/html", html),
+        ("resource", Some(url), "image/png", screenshot),
+        ("metadata", Some(url), "application/json", metadata.as_bytes()),
+    ];
+
+    if let Some(refetch) = refetch {
+        records.push(("metadata", Some(url), "application/warc-fields", refetch.as_bytes()));
+    }
+
+    let dom_id = record_id();
+    let mut warc = vec![];
+
+    for (n, (kind, target, content_type, block)) in records.into_iter().enumerate() {
+        let id = match n {
+            1 => dom_id.clone(),
+            _ => record_id(),
+        };
+
+        let mut header = format!(
+            "WARC/1.1\r\nWARC-Type: {}\r\nWARC-Record-ID: <urn:uuid:{}>\r\nWARC-Date: {}\r\n",
+            kind, id, date
+        );
+
+        if let Some(target) = target {
+            header.push_str(format!("WARC-Target-URI: {}\r\n", target).as_str());
+        }
+
+        if n > 1 {
+            header.push_str(format!("WARC-Concurrent-To: <urn:uuid:{}>\r\n", dom_id).as_str());
+        }
+
+        header.push_str(
+            format!(
+                "WARC-Block-Digest: sha256:{}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                sha256_hex(block),
+                content_type,
+                block.len()
+            )
+            .as_str(),
+        );
+
+        warc.extend_from_slice(header.as_bytes());
+        warc.extend_from_slice(block);
+        warc.extend_from_slice(b"\r\n\r\n");
+    }
+
+    warc
+}
+
+/// A random version 4 UUID.
+pub fn record_id() -> String {
+    let bits = (rand::random::<u128>() & !(0xf000u128 << 64) & !(0xc000u128 << 48))
+        | (0x4000u128 << 64)
+        | (0x8000u128 << 48);
+    let hex = format!("{:032x}", bits);
+
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
@@ -1,8 +1,21 @@
+#[cfg(feature = "http-api")]
+use crate::api::ApiConfig;
+use crate::content_policy::ContentPolicy;
 use crate::cookie::Cookie;
+use crate::disclosure::DisclosurePolicy;
+use crate::failure::FailureArtifacts;
+use crate::health::HealthCheck;
+use crate::job_file::JobFile;
+use crate::quota::QuotaConfig;
+use crate::rate_limit::{RateLimitConfig, RateLimiter};
+use crate::retry::RetryPolicy;
+use crate::utils::bot_error;
+use chrono::Utc;
 use mongodb::{Client, Database};
 use rand::{self, Rng};
 use serde::{Deserialize, Serialize};
 use serde_json::from_str;
+use std::path::Path;
 use thirtyfour::{error::WebDriverResult, WebDriver};
 
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
@@ -19,6 +32,8 @@ pub struct Behavior {
     erratic_reload: Erracy,
     wait_rng_min: u8,
     wait_rng_max: u8,
+    #[serde(default)]
+    pub retry: RetryPolicy,
 }
 
 impl Behavior {
@@ -116,24 +131,154 @@ impl Behavior {
 }
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
 pub struct Config {
+    #[serde(default)]
+    pub account: String,
     pub cookies: Vec<Cookie>,
+    /// Extra cookies in any format `Cookie::import` reads, on top of `cookies`.
+    #[serde(default)]
+    pub cookie_file: Option<String>,
+    /// The live session's cookies are saved here and loaded last on the next start.
+    #[serde(default)]
+    pub cookie_store: Option<String>,
+    #[serde(default)]
+    pub cookie_refresh_minutes: Option<u64>,
     pub behavior: Behavior,
     pub selenium_url: String,
     pub mongodb_uri: String,
     pub mongodb_db_name: String,
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    #[serde(default)]
+    pub kill_sentinel: Option<String>,
+    #[serde(default)]
+    pub disclosure: DisclosurePolicy,
+    #[serde(default)]
+    pub content_policy: ContentPolicy,
+    #[serde(default)]
+    pub quotas: QuotaConfig,
+    #[serde(default)]
+    pub health: HealthCheck,
+    #[serde(default)]
+    pub failure_artifacts: FailureArtifacts,
+    /// Pending approvals expire after this long, a day when unset.
+    #[serde(default)]
+    pub approval_timeout_minutes: Option<u64>,
+    /// TOML or YAML jobs the daemon keeps the job store in line with.
+    #[serde(default)]
+    pub jobs_file: Option<String>,
+    /// The control API is only served when this is set.
+    #[cfg(feature = "http-api")]
+    #[serde(default)]
+    pub api: Option<ApiConfig>,
 }
 
 impl Config {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: String) -> Self {
-        let config: Config = from_str(s.as_str()).unwrap();
+        from_str(s.as_str()).unwrap()
+    }
 
-        config
+    pub fn parse(s: &str) -> serde_json::Result<Self> {
+        from_str(s)
+    }
+
+    /// Mistakes that parse fine but would only show up once the bot runs.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        if !self.selenium_url.starts_with("http") {
+            problems.push(format!("selenium_url {:?} is not an http url", self.selenium_url));
+        }
+
+        if !self.mongodb_uri.starts_with("mongodb") {
+            problems.push(format!("mongodb_uri {:?} is not a mongodb uri", self.mongodb_uri));
+        }
+
+        if self.account.is_empty() {
+            problems.push(String::from("account is empty, so a wrong login goes unnoticed"));
+        }
+
+        if self.behavior.wait_rng_min >= self.behavior.wait_rng_max {
+            problems.push(String::from("behavior.wait_rng_min must be below wait_rng_max"));
+        }
+
+        match self.all_cookies() {
+            Ok(cookies) if cookies.is_empty() => problems.push(String::from("no cookies")),
+            Ok(cookies) => {
+                let expired = Cookie::warn_expired(&cookies, Utc::now());
+
+                if expired > 0 {
+                    problems.push(format!("{} of {} cookies expired", expired, cookies.len()));
+                }
+            }
+            Err(e) => problems.push(format!("cookies could not be loaded: {}", e)),
+        }
+
+        if let Some(parent) = self.cookie_store.as_ref().and_then(|x| Path::new(x).parent()) {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                problems.push(format!("cookie_store directory {:?} does not exist", parent));
+            }
+        }
+
+        if let Some(path) = &self.jobs_file {
+            if let Err(e) = JobFile::new(path).load() {
+                problems.extend(e.problems());
+            }
+        }
+
+        #[cfg(feature = "http-api")]
+        if let Some(api) = &self.api {
+            if std::env::var(api.token_env.as_str()).unwrap_or_default().is_empty() {
+                problems.push(format!("api.token_env: ${} is not set", api.token_env));
+            }
+        }
+
+        problems
+    }
+
+    #[allow(clippy::result_large_err)]
+    pub fn all_cookies(&self) -> WebDriverResult<Vec<Cookie>> {
+        let mut cookies = self.cookies.clone();
+
+        Cookie::warn_expired(&cookies, Utc::now());
+
+        if let Some(path) = &self.cookie_file {
+            let imported = Cookie::import(path.as_str())
+                .map_err(|e| bot_error(e.to_string()))?;
+
+            cookies.retain(|x| !imported.iter().any(|y| y.same_key(x)));
+            cookies.extend(imported);
+        }
+
+        if let Some(path) = &self.cookie_store {
+            if Path::new(path.as_str()).exists() {
+                let stored = Cookie::try_from_file(path.as_str())
+                    .map_err(|e| bot_error(e.to_string()))?;
+
+                Cookie::warn_expired(&stored, Utc::now());
+
+                cookies.retain(|x| !stored.iter().any(|y| y.same_key(x)));
+                cookies.extend(stored);
+            }
+        }
+
+        Ok(cookies)
     }
 
     pub async fn apply_config(&self, driver: &WebDriver) -> WebDriverResult<()> {
-        self.behavior.run_erratic_reload(&driver).await?;
-        self.behavior.run_erratic_scroll(&driver).await?;
-        Cookie::add_all_cookies(&driver, self.cookies.clone()).await?;
+        RateLimiter::configure(self.rate_limits.clone()).await;
+        self.behavior.run_erratic_reload(driver).await?;
+        self.behavior.run_erratic_scroll(driver).await?;
+        let report = Cookie::add_all_cookies(driver, self.all_cookies()?).await?;
+
+        for failure in report.failed.iter() {
+            tracing::warn!(
+                name = failure.name.as_str(),
+                domain = failure.domain.as_deref().unwrap_or_default(),
+                error = failure.error.as_str(),
+                "cookie was not added"
+            );
+        }
 
         Ok(())
     }
@@ -142,8 +287,7 @@ impl Config {
         let client = Client::with_uri_str(self.mongodb_uri.as_str())
             .await
             .unwrap();
-        let database = client.database(self.mongodb_db_name.as_str());
 
-        database
+        client.database(self.mongodb_db_name.as_str())
     }
 }
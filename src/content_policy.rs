@@ -0,0 +1,246 @@
+use crate::action::Action;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use levenshtein::levenshtein;
+use mongodb::bson::doc;
+use mongodb::options::FindOptions;
+use mongodb::Database;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+const POSTED_COLL_NAME: &str = "posted-texts";
+
+lazy_static! {
+    static ref RE_LINK: Regex = Regex::new(r#"https?://\S+"#).unwrap();
+    static ref RE_MENTION: Regex = Regex::new(r#"(^|[^\w])@\w+"#).unwrap();
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum CheckKind {
+    Length,
+    BannedTerm,
+    TooManyLinks,
+    TooManyMentions,
+    NearDuplicate,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Flag,
+    Reject,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct ContentFinding {
+    pub check: CheckKind,
+    pub severity: Severity,
+    pub detail: String,
+}
+
+#[derive(Serialize, Clone, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct ContentReview {
+    pub findings: Vec<ContentFinding>,
+}
+
+impl ContentReview {
+    pub fn is_rejected(&self) -> bool {
+        self.findings.iter().any(|x| x.severity == Severity::Reject)
+    }
+
+    pub fn summary(&self) -> String {
+        self.findings
+            .iter()
+            .map(|x| format!("{:?}: {}", x.check, x.detail))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ContentPolicy {
+    pub max_length: usize,
+    pub banned_terms: Vec<String>,
+    pub max_links: usize,
+    pub max_mentions: usize,
+    pub near_duplicate_percent: u32,
+    pub recent_window: i64,
+    pub flag_only: Vec<CheckKind>,
+}
+
+impl Default for ContentPolicy {
+    fn default() -> Self {
+        ContentPolicy {
+            max_length: 280,
+            banned_terms: vec![],
+            max_links: 2,
+            max_mentions: 3,
+            near_duplicate_percent: 90,
+            recent_window: 50,
+            flag_only: vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct PostedText {
+    pub account: String,
+    pub text: String,
+    pub at: DateTime<Utc>,
+}
+
+impl ContentPolicy {
+    fn finding(&self, check: CheckKind, detail: String) -> ContentFinding {
+        let severity = if check != CheckKind::Length && self.flag_only.contains(&check) {
+            Severity::Flag
+        } else {
+            Severity::Reject
+        };
+
+        ContentFinding {
+            check,
+            severity,
+            detail,
+        }
+    }
+
+    /// `ignore` is stripped before comparing against `recent`, so a shared label does not
+    /// make every short post look like a duplicate.
+    pub fn check(&self, text: &str, recent: &[String], ignore: &str) -> ContentReview {
+        let mut findings = vec![];
+
+        let length = text.chars().count();
+
+        if length > self.max_length {
+            findings.push(self.finding(
+                CheckKind::Length,
+                format!("{} characters, limit is {}", length, self.max_length),
+            ));
+        }
+
+        let lowered = text.to_lowercase();
+
+        for term in self.banned_terms.iter() {
+            if lowered.contains(term.to_lowercase().as_str()) {
+                let detail = format!("contains \"{}\"", term);
+
+                findings.push(self.finding(CheckKind::BannedTerm, detail));
+            }
+        }
+
+        let links = RE_LINK.find_iter(text).count();
+
+        if links > self.max_links {
+            findings.push(self.finding(
+                CheckKind::TooManyLinks,
+                format!("{} links, limit is {}", links, self.max_links),
+            ));
+        }
+
+        let mentions = RE_MENTION.find_iter(text).count();
+
+        if mentions > self.max_mentions {
+            findings.push(self.finding(
+                CheckKind::TooManyMentions,
+                format!("{} mentions, limit is {}", mentions, self.max_mentions),
+            ));
+        }
+
+        let stripped = normalize(text, ignore);
+
+        for previous in recent.iter() {
+            let previous = normalize(previous, ignore);
+            let longest = stripped.chars().count().max(previous.chars().count());
+
+            if longest == 0 {
+                continue;
+            }
+
+            let distance = levenshtein(stripped.as_str(), previous.as_str());
+            let similarity = 100 - (distance * 100 / longest) as u32;
+
+            if similarity >= self.near_duplicate_percent {
+                findings.push(self.finding(
+                    CheckKind::NearDuplicate,
+                    format!("{}% similar to \"{}\"", similarity, previous),
+                ));
+
+                break;
+            }
+        }
+
+        ContentReview { findings }
+    }
+
+    pub async fn recent_posts(&self, db: &Database, account: &str) -> Vec<String> {
+        let options = FindOptions::builder()
+            .sort(doc! {"at": -1})
+            .limit(self.recent_window)
+            .build();
+
+        let found = match db
+            .collection::<PostedText>(POSTED_COLL_NAME)
+            .find(doc! {"account": account}, options)
+            .await
+        {
+            Ok(cursor) => cursor.try_collect::<Vec<PostedText>>().await,
+            Err(e) => Err(e),
+        };
+
+        match found {
+            Ok(posted) => posted.into_iter().map(|x| x.text).collect(),
+            Err(e) => {
+                tracing::warn!(error = %e, "could not load recent posts for duplicate check");
+
+                vec![]
+            }
+        }
+    }
+
+    /// Reviews the text an action would publish. Actions that publish no text get `None`.
+    pub async fn review_action(
+        &self,
+        db: &Database,
+        account: &str,
+        action: &Action,
+        ignore: &str,
+    ) -> Option<ContentReview> {
+        if !action.is_publishing() {
+            return None;
+        }
+
+        let text = action.content().unwrap_or_default();
+        let recent = self.recent_posts(db, account).await;
+
+        Some(self.check(text.as_str(), &recent, ignore))
+    }
+
+    pub async fn remember_posted(db: &Database, account: &str, text: &str) {
+        let posted = PostedText {
+            account: account.to_string(),
+            text: text.to_string(),
+            at: Utc::now(),
+        };
+
+        if let Err(e) = db
+            .collection::<PostedText>(POSTED_COLL_NAME)
+            .insert_one(&posted, None)
+            .await
+        {
+            tracing::warn!(error = %e, "could not remember posted text");
+        }
+    }
+}
+
+fn normalize(text: &str, ignore: &str) -> String {
+    let text = if ignore.is_empty() {
+        text.to_string()
+    } else {
+        text.replace(ignore, "")
+    };
+
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
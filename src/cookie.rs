@@ -1,8 +1,175 @@
-use crate::utils::read_from_file;
+use crate::rate_limit::{limited_get, OpKind};
+use crate::utils::{bot_error, read_from_file, write_to_file_atomic};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use serde_json::from_str;
-use thirtyfour::common::cookie::Cookie as TFCookie;
-use thirtyfour::{error::WebDriverResult, WebDriver};
+use serde_json::{from_str, json, Map, Value};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::result::Result;
+use thirtyfour::error::WebDriverResult;
+use thirtyfour::{ExtensionCommand, RequestMethod, WebDriver};
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum CookieFormat {
+    /// A JSON array: this crate's own shape, or what cookie-editing browser extensions export.
+    Json,
+    /// `cookies.txt` as written by curl, wget and most "export cookies" extensions.
+    Netscape,
+    Har,
+}
+
+impl CookieFormat {
+    pub fn detect(path: &str, contents: &str) -> Self {
+        let trimmed = contents.trim_start();
+
+        match Path::new(path).extension().and_then(|x| x.to_str()) {
+            Some("har") => CookieFormat::Har,
+            Some("txt") => CookieFormat::Netscape,
+            _ if trimmed.starts_with('[') => CookieFormat::Json,
+            _ if trimmed.starts_with('{') => CookieFormat::Har,
+            _ => CookieFormat::Netscape,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CookieImportError {
+    details: String,
+}
+
+impl fmt::Display for CookieImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for CookieImportError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl CookieImportError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = CookieImportError {
+            details: details.to_string(),
+        };
+
+        Box::from(err)
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct CookieFailure {
+    pub name: String,
+    pub domain: Option<String>,
+    pub error: String,
+}
+
+#[derive(Serialize, Clone, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct CookieLoadReport {
+    pub added: usize,
+    pub failed: Vec<CookieFailure>,
+}
+
+impl CookieLoadReport {
+    fn fail(&mut self, cookie: &Cookie, error: String) {
+        self.failed.push(CookieFailure {
+            name: cookie.name.clone(),
+            domain: cookie.domain.clone(),
+            error,
+        });
+    }
+}
+
+/// The WebDriver cookie commands, sent as they are. thirtyfour's own `Cookie` has no
+/// `httpOnly`, so going through it would drop the flag both ways.
+#[derive(Debug)]
+enum CookieCommand {
+    Add(Value),
+    GetAll,
+}
+
+impl ExtensionCommand for CookieCommand {
+    fn parameters_json(&self) -> Option<Value> {
+        match self {
+            CookieCommand::Add(cookie) => Some(json!({ "cookie": cookie })),
+            CookieCommand::GetAll => None,
+        }
+    }
+
+    fn method(&self) -> RequestMethod {
+        match self {
+            CookieCommand::Add(_) => RequestMethod::Post,
+            CookieCommand::GetAll => RequestMethod::Get,
+        }
+    }
+
+    fn endpoint(&self) -> String {
+        String::from("/cookie")
+    }
+}
+
+/// One cookie as browser extensions export them. Also reads this crate's own shape.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExportedCookie {
+    name: String,
+    value: String,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: Option<bool>,
+    http_only: Option<bool>,
+    same_site: Option<String>,
+    expiration_date: Option<f64>,
+    #[serde(alias = "expiry")]
+    expires: Option<Value>,
+    session: Option<bool>,
+}
+
+impl From<ExportedCookie> for Cookie {
+    fn from(exported: ExportedCookie) -> Self {
+        let expires = match exported.session {
+            Some(true) => None,
+            _ => exported
+                .expiration_date
+                .map(|x| x as i64)
+                .or_else(|| exported.expires.as_ref().and_then(parse_expires)),
+        };
+
+        Cookie {
+            name: exported.name,
+            value: exported.value,
+            domain: exported.domain,
+            path: exported.path,
+            expires,
+            httpOnly: exported.http_only,
+            secure: exported.secure,
+            sameSite: exported.same_site.as_deref().and_then(normalize_same_site),
+        }
+    }
+}
+
+/// Accepts unix seconds or an ISO 8601 date, which is what HAR files use.
+fn parse_expires(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_f64().map(|x| x as i64),
+        Value::String(s) => DateTime::parse_from_rfc3339(s.as_str())
+            .ok()
+            .map(|x| x.timestamp()),
+        _ => None,
+    }
+}
+
+fn normalize_same_site(same_site: &str) -> Option<String> {
+    match same_site.to_ascii_lowercase().as_str() {
+        "strict" => Some(String::from("Strict")),
+        "lax" => Some(String::from("Lax")),
+        "none" | "no_restriction" => Some(String::from("None")),
+        _ => None,
+    }
+}
 
 #[derive(Serialize, Clone, Deserialize, Default, Debug, PartialEq, Eq)]
 #[allow(non_snake_case)]
@@ -14,15 +181,23 @@ pub struct Cookie {
     pub expires: Option<i64>,
     pub httpOnly: Option<bool>,
     pub secure: Option<bool>,
+    pub sameSite: Option<String>,
 }
 
 impl Cookie {
     pub fn from_file(fpath: &str) -> Vec<Self> {
-        let contents = read_from_file(fpath).unwrap();
+        Self::try_from_file(fpath).unwrap()
+    }
 
-        let cookie: Vec<Cookie> = from_str(contents.as_str()).unwrap();
+    /// Like `from_file`, but a missing or malformed file is an error instead of a panic.
+    pub fn try_from_file(fpath: &str) -> Result<Vec<Self>, Box<CookieImportError>> {
+        let contents = read_from_file(fpath)
+            .map_err(|e| CookieImportError::new(format!("{}: {}", fpath, e).as_str()))?;
 
-        cookie
+        let cookie: Vec<Cookie> = from_str(contents.as_str())
+            .map_err(|e| CookieImportError::new(format!("{}: {}", fpath, e).as_str()))?;
+
+        Ok(cookie)
     }
 
     pub fn from_string(str: &str) -> Vec<Self> {
@@ -31,48 +206,280 @@ impl Cookie {
         cookie
     }
 
-    fn convert_cookie(self) -> TFCookie {
-        let mut tf_cookie: TFCookie =
-            TFCookie::new(self.name.as_str(), serde_json::json!(self.value.as_str()));
+    pub fn from_json_export(str: &str) -> Result<Vec<Self>, Box<CookieImportError>> {
+        let exported: Vec<ExportedCookie> =
+            from_str(str).map_err(|e| CookieImportError::new(e.to_string().as_str()))?;
+
+        Ok(exported.into_iter().map(Cookie::from).collect())
+    }
+
+    pub fn from_netscape(str: &str) -> Result<Vec<Self>, Box<CookieImportError>> {
+        let mut cookies = vec![];
+
+        for (number, line) in str.lines().enumerate() {
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(rest) => (rest, true),
+                None => (line, false),
+            };
+
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+
+            if fields.len() != 7 {
+                return Err(CookieImportError::new(
+                    format!("line {} has {} fields, expected 7", number + 1, fields.len()).as_str(),
+                ));
+            }
+
+            // A zero expiry marks a session cookie.
+            let expires = fields[4].parse::<i64>().ok().filter(|x| *x > 0);
+
+            cookies.push(Cookie {
+                name: fields[5].to_string(),
+                value: fields[6].to_string(),
+                domain: Some(fields[0].to_string()),
+                path: Some(fields[2].to_string()),
+                expires,
+                httpOnly: Some(http_only),
+                secure: Some(fields[3].eq_ignore_ascii_case("TRUE")),
+                sameSite: None,
+            });
+        }
+
+        Ok(cookies)
+    }
+
+    /// Collects the cookies of every request and response in a HAR file. A cookie seen
+    /// more than once keeps its last value.
+    pub fn from_har(str: &str) -> Result<Vec<Self>, Box<CookieImportError>> {
+        let har: Value = from_str(str).map_err(|e| CookieImportError::new(e.to_string().as_str()))?;
+
+        let entries = har["log"]["entries"]
+            .as_array()
+            .ok_or_else(|| CookieImportError::new("HAR file has no log.entries"))?;
+
+        let mut cookies: Vec<Cookie> = vec![];
+
+        for entry in entries {
+            for side in ["request", "response"] {
+                let listed = match entry[side]["cookies"].as_array() {
+                    Some(listed) => listed,
+                    None => continue,
+                };
+
+                for raw in listed {
+                    let exported: ExportedCookie = match serde_json::from_value(raw.clone()) {
+                        Ok(exported) => exported,
+                        Err(_) => continue,
+                    };
+                    let cookie = Cookie::from(exported);
+
+                    cookies.retain(|x| !x.same_key(&cookie));
+                    cookies.push(cookie);
+                }
+            }
+        }
+
+        Ok(cookies)
+    }
+
+    /// Reads cookies from `fpath` in any supported format, warning about expired ones.
+    pub fn import(fpath: &str) -> Result<Vec<Self>, Box<CookieImportError>> {
+        let contents =
+            read_from_file(fpath).map_err(|e| CookieImportError::new(e.to_string().as_str()))?;
+
+        let cookies = match CookieFormat::detect(fpath, contents.as_str()) {
+            CookieFormat::Json => Self::from_json_export(contents.as_str())?,
+            CookieFormat::Netscape => Self::from_netscape(contents.as_str())?,
+            CookieFormat::Har => Self::from_har(contents.as_str())?,
+        };
+
+        Self::warn_expired(&cookies, Utc::now());
+
+        Ok(cookies)
+    }
+
+    /// Whether both cookies would occupy the same slot in the browser.
+    pub fn same_key(&self, other: &Self) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.expires, Some(expires) if expires <= now.timestamp())
+    }
+
+    /// Logs every cookie that has already expired and returns how many there were.
+    pub fn warn_expired(cookies: &[Self], now: DateTime<Utc>) -> usize {
+        let expired: Vec<&Cookie> = cookies.iter().filter(|x| x.is_expired(now)).collect();
+
+        for cookie in expired.iter() {
+            tracing::warn!(
+                name = cookie.name.as_str(),
+                domain = cookie.domain.as_deref().unwrap_or_default(),
+                "cookie has already expired"
+            );
+        }
+
+        expired.len()
+    }
+
+    /// Reads the cookies the browser holds right now, including values refreshed
+    /// during the session.
+    pub async fn export_from_driver(wd: &WebDriver) -> WebDriverResult<Vec<Self>> {
+        let mut cookies = vec![];
 
-        if let Some(_) = self.domain {
-            tf_cookie.set_domain(self.domain);
+        let all = wd.extension_command(CookieCommand::GetAll).await?;
+
+        for wire in all.as_array().cloned().unwrap_or_default() {
+            match serde_json::from_value::<ExportedCookie>(wire) {
+                Ok(exported) => cookies.push(Cookie::from(exported)),
+                Err(e) => tracing::warn!(error = %e, "skipping cookie the browser returned"),
+            }
         }
 
-        if let Some(_) = self.path {
-            tf_cookie.set_path(self.path);
+        Ok(cookies)
+    }
+
+    /// Saves `cookies` in the shape `from_file` reads.
+    pub fn save_to_file(fpath: &str, cookies: &[Self]) -> std::io::Result<()> {
+        write_to_file_atomic(fpath, serde_json::to_string_pretty(cookies).unwrap())
+    }
+
+    pub async fn export_to_file(wd: &WebDriver, fpath: &str) -> WebDriverResult<usize> {
+        let cookies = Self::export_from_driver(wd).await?;
+
+        Self::save_to_file(fpath, &cookies)
+            .map_err(|e| bot_error(e.to_string()))?;
+
+        Ok(cookies.len())
+    }
+
+    /// The cookie as the WebDriver "Add Cookie" command takes it. Unset fields are left
+    /// out, and a `sameSite` the protocol does not know is an error.
+    pub fn to_wire(&self) -> Result<Value, String> {
+        let mut wire = Map::new();
+
+        wire.insert(String::from("name"), json!(self.name));
+        wire.insert(String::from("value"), json!(self.value));
+
+        let optional = [
+            ("domain", self.domain.as_ref().map(|x| json!(x))),
+            ("path", self.path.as_ref().map(|x| json!(x))),
+            ("secure", self.secure.map(|x| json!(x))),
+            ("httpOnly", self.httpOnly.map(|x| json!(x))),
+            ("expiry", self.expires.map(|x| json!(x))),
+        ];
+
+        for (key, value) in optional {
+            if let Some(value) = value {
+                wire.insert(String::from(key), value);
+            }
         }
 
-        if let Some(_) = self.secure {
-            tf_cookie.set_secure(self.secure);
+        if let Some(same_site) = &self.sameSite {
+            if !["Strict", "Lax", "None"].contains(&same_site.as_str()) {
+                return Err(format!("sameSite {:?} is not Strict, Lax or None", same_site));
+            }
+
+            wire.insert(String::from("sameSite"), json!(same_site));
         }
 
-        tf_cookie
+        Ok(Value::Object(wire))
     }
 
-    pub async fn add_all_cookies(wd: &WebDriver, cookies: Vec<Self>) -> WebDriverResult<()> {
+    /// Groups cookies by the host they belong to, in the order hosts first appear.
+    /// Cookies without a domain are grouped under an empty host.
+    pub fn group_by_domain(cookies: Vec<Self>) -> Vec<(String, Vec<Self>)> {
+        let mut groups: Vec<(String, Vec<Self>)> = vec![];
+
         for cookie in cookies {
-            let tf_cookie: TFCookie = cookie.convert_cookie();
+            let host = cookie
+                .domain
+                .as_deref()
+                .unwrap_or_default()
+                .trim_start_matches('.')
+                .to_string();
 
-            wd.add_cookie(tf_cookie).await?;
+            match groups.iter_mut().find(|(x, _)| *x == host) {
+                Some((_, group)) => group.push(cookie),
+                None => groups.push((host, vec![cookie])),
+            }
         }
 
-        Ok(())
+        groups
     }
 
-    pub async fn load_from_str_and_add(str: &str, wd: &WebDriver) -> WebDriverResult<()> {
-        let cookies = Self::from_string(str);
+    /// WebDriver only accepts cookies for the page it is on, so this opens each domain's
+    /// origin before adding its cookies. A failing cookie is reported and skipped.
+    pub async fn add_all_cookies(
+        wd: &WebDriver,
+        cookies: Vec<Self>,
+    ) -> WebDriverResult<CookieLoadReport> {
+        let mut report = CookieLoadReport::default();
+        let now = Utc::now();
+
+        let mut groups = Self::group_by_domain(cookies);
+
+        // Cookies without a domain go on whichever page the last group left open.
+        groups.sort_by_key(|(host, _)| host.is_empty());
+
+        for (host, group) in groups {
+            if !host.is_empty() {
+                let origin = format!("https://{}/", host);
+
+                if let Err(e) = limited_get(wd, origin, OpKind::Record).await {
+                    for cookie in group {
+                        report.fail(&cookie, format!("could not open {}: {}", host, e));
+                    }
+
+                    continue;
+                }
+            }
+
+            for cookie in group {
+                if cookie.is_expired(now) {
+                    report.fail(&cookie, String::from("expired"));
 
-        Self::add_all_cookies(wd, cookies).await?;
+                    continue;
+                }
 
-        Ok(())
+                let wire = match cookie.to_wire() {
+                    Ok(wire) => wire,
+                    Err(e) => {
+                        report.fail(&cookie, e);
+
+                        continue;
+                    }
+                };
+
+                match wd.extension_command(CookieCommand::Add(wire)).await {
+                    Ok(_) => report.added += 1,
+                    Err(e) => report.fail(&cookie, e.to_string()),
+                }
+            }
+        }
+
+        Ok(report)
     }
 
-    pub async fn load_from_file_and_add(floc: &str, wd: &WebDriver) -> WebDriverResult<()> {
-        let cookies = Self::from_file(floc);
+    pub async fn load_from_str_and_add(
+        str: &str,
+        wd: &WebDriver,
+    ) -> WebDriverResult<CookieLoadReport> {
+        let cookies = Self::from_string(str);
+
+        Self::add_all_cookies(wd, cookies).await
+    }
 
-        Self::add_all_cookies(wd, cookies).await?;
+    pub async fn load_from_file_and_add(
+        floc: &str,
+        wd: &WebDriver,
+    ) -> WebDriverResult<CookieLoadReport> {
+        let cookies = Self::try_from_file(floc).map_err(|e| bot_error(e.to_string()))?;
 
-        Ok(())
+        Self::add_all_cookies(wd, cookies).await
     }
 }
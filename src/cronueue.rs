@@ -1,12 +1,48 @@
-use crate::{action::Action, config::Behavior};
+use crate::approval::{ApprovalQueue, ApprovalStatus};
+use crate::dry_run::{DryRunReport, ExecMode, StepLog};
+use crate::failure::link_artifacts;
+use crate::health::{raise_alert, session_alert};
+use crate::jobs::JobStore;
+use crate::quota::{OverQuota, Quotas};
+use crate::session::SessionManager;
+use crate::audit::{AuditLog, AuditOutcome};
+use crate::cancel::JobControl;
+use crate::content_policy::{ContentPolicy, ContentReview};
+use crate::utils::bot_error;
+use crate::{action::Action, config::Config};
 use chrono::prelude::*;
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, TryRecvError};
 use mongodb::Database;
 use serde::{Deserialize, Serialize};
 use std::mem::drop;
+use std::sync::Arc;
 use std::time::Duration;
 use thirtyfour::{prelude::WebDriverResult, WebDriver};
-use tokio::{sync::Mutex, time::sleep};
+use tokio::sync::Mutex;
+
+const APPROVAL_POLL: Duration = Duration::from_secs(30);
+const APPROVAL_TIMEOUT_MINUTES: u64 = 24 * 60;
+const SCHEDULER_TICK: Duration = Duration::from_millis(500);
+const SKIPPED_RETRY: Duration = Duration::from_secs(60);
+
+/// What became of one firing of a job. Only `Ran` counts as a run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Fired {
+    Ran(AuditOutcome),
+    /// Given up for this slot: over quota with `Drop`, rejected, or not publishable.
+    Dropped,
+    /// Could not run yet, e.g. the page was unreachable; the same slot is tried again.
+    Skipped,
+}
+
+impl Fired {
+    pub fn outcome(self) -> Option<AuditOutcome> {
+        match self {
+            Fired::Ran(outcome) => Some(outcome),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
 pub enum ExecType {
@@ -20,6 +56,12 @@ pub struct CronueueAction {
     exec_time: DateTime<Utc>,
     action: Action,
     exec_type: ExecType,
+    #[serde(default)]
+    mode: ExecMode,
+    /// Seconds between runs of a `Multiple` or `Forever` job. Without it the job fires on
+    /// every scheduler tick once `exec_time` has passed.
+    #[serde(default)]
+    every: Option<u64>,
 }
 
 impl CronueueAction {
@@ -28,78 +70,565 @@ impl CronueueAction {
             exec_time,
             action,
             exec_type,
+            mode: ExecMode::Live,
+            every: None,
         }
     }
 
-    pub async fn run_queue(
+    pub fn with_mode(mut self, mode: ExecMode) -> Self {
+        self.mode = mode;
+
+        self
+    }
+
+    pub fn with_every(mut self, secs: u64) -> Self {
+        self.every = Some(secs);
+
+        self
+    }
+
+    pub fn action(&self) -> &Action {
+        &self.action
+    }
+
+    pub fn exec_type(&self) -> &ExecType {
+        &self.exec_type
+    }
+
+    pub fn mode(&self) -> &ExecMode {
+        &self.mode
+    }
+
+    /// When the job fires next, given how many of its slots are used up. A dropped run uses
+    /// its slot like a run that went through. Slots missed while nothing was running are
+    /// skipped, apart from one catch-up run.
+    pub fn next_fire(
         &self,
-        driver_arc_mutex: Mutex<WebDriver>,
-        behavior_arc_mutex: Mutex<Behavior>,
-        db_arc_mutex: Mutex<Database>,
-        receiver: &Receiver<u32>,
-    ) -> WebDriverResult<()> {
-        let mut times_ran = 0u32;
+        slots_used: u32,
+        last_run: Option<DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        let done = match self.exec_type {
+            ExecType::Once => slots_used >= 1,
+            ExecType::Multiple(num) => slots_used >= num,
+            ExecType::Forever => false,
+        };
+
+        if done {
+            return None;
+        }
+
+        let every = match self.every {
+            Some(secs) => chrono::Duration::seconds(secs.max(1) as i64),
+            // Unspaced jobs fire on every scheduler tick until they are done.
+            None => return Some(self.exec_time),
+        };
+
+        let slot = self.exec_time + every * slots_used as i32;
+
+        match last_run {
+            Some(last_run) if slot <= last_run => {
+                let missed = (last_run - self.exec_time).num_seconds() / every.num_seconds();
+
+                Some(self.exec_time + every * (missed + 1) as i32)
+            }
+            _ => Some(slot),
+        }
+    }
+
+    /// Waits for a person to sign off publishing actions. `None` means the run was
+    /// rejected, or nobody decided before the approval timeout.
+    async fn approved_action(
+        &self,
+        name: &str,
+        action: Action,
+        review: Option<ContentReview>,
+        config_arc_mutex: &Mutex<Config>,
+        db_arc_mutex: &Mutex<Database>,
+        control: &JobControl,
+    ) -> Option<(Action, Option<String>)> {
+        if !action.needs_approval() || self.mode != ExecMode::Live {
+            return Some((action, None));
+        }
+
+        let (account, timeout) = {
+            let config = config_arc_mutex.lock().await;
+            let minutes = config.approval_timeout_minutes.unwrap_or(APPROVAL_TIMEOUT_MINUTES);
+
+            (config.account.clone(), chrono::Duration::minutes(minutes as i64))
+        };
+
+        let submitted = {
+            let db = db_arc_mutex.lock().await;
+
+            ApprovalQueue::new(&db).submit(name, account.as_str(), &action, review).await
+        };
+
+        let id = match submitted {
+            Ok(id) => id,
+            Err(e) => {
+                tracing::error!(job = name, error = %e, "could not queue action for approval");
+
+                return None;
+            }
+        };
 
         loop {
-            match receiver.recv() {
-                Ok(u32_sent) => {
-                    if u32_sent == 0 {
-                        break;
-                    } else {
-                        sleep(Duration::from_millis(u32_sent.into())).await;
+            if control.sleep(APPROVAL_POLL).await.is_err() {
+                return None;
+            }
+
+            let db = db_arc_mutex.lock().await;
+
+            let queue = ApprovalQueue::new(&db);
+
+            match queue.get(id.as_str()).await {
+                Ok(Some(pending)) => match pending.status {
+                    ApprovalStatus::Approved => return Some((pending.action, Some(id))),
+                    ApprovalStatus::Pending if Utc::now() >= pending.expires_at(timeout) => {
+                        // Fails when a decision came in meanwhile; the next poll reads it.
+                        if queue.expire(id.as_str()).await.is_ok() {
+                            tracing::warn!(job = name, id, "nobody approved in time, skipping");
+
+                            return None;
+                        }
                     }
+                    ApprovalStatus::Pending => {}
+                    _ => return None,
+                },
+                Ok(None) => return None,
+                Err(e) => {
+                    tracing::warn!(job = name, error = %e, "could not read approval state");
                 }
-                Err(_) => panic!("Bad cross-thread message"),
             }
+        }
+    }
 
-            let time_now = Utc::now();
+    /// Checks the account's quota for this run. Over-quota runs are deferred until the
+    /// window resets or dropped, depending on the config; `Err` says how the run ended.
+    async fn within_quota(
+        &self,
+        name: &str,
+        config_arc_mutex: &Mutex<Config>,
+        db_arc_mutex: &Mutex<Database>,
+        control: &JobControl,
+    ) -> Result<(), Fired> {
+        if self.mode != ExecMode::Live {
+            return Ok(());
+        }
+
+        loop {
+            let (exceeded, over_quota) = {
+                let config = config_arc_mutex.lock().await;
+                let db = db_arc_mutex.lock().await;
+
+                let exceeded = Quotas::new(&db)
+                    .check(&config.quotas, config.account.as_str(), &self.action, Utc::now())
+                    .await;
 
-            if time_now == self.exec_time {
-                match self.exec_type {
-                    ExecType::Once => {
-                        let driver = driver_arc_mutex.lock().await;
-                        let behavior = behavior_arc_mutex.lock().await;
-                        let db = db_arc_mutex.lock().await;
+                (exceeded, config.quotas.over_quota.clone())
+            };
 
-                        self.action.clone().call(&driver, &behavior, &db).await?;
+            let exceeded = match exceeded {
+                Ok(Some(exceeded)) => exceeded,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    tracing::error!(job = name, error = %e, "could not read quota, skipping run");
 
-                        drop(driver);
-                        drop(behavior);
-                        drop(db);
+                    return Err(Fired::Skipped);
+                }
+            };
+
+            let reason = exceeded.to_string();
+
+            match over_quota {
+                OverQuota::Drop => {
+                    tracing::info!(job = name, reason, "over quota, dropping this run");
+
+                    return Err(Fired::Dropped);
+                }
+                OverQuota::Defer => {
+                    tracing::info!(job = name, reason, "over quota, deferring this run");
+
+                    let wait = (exceeded.resets_at - Utc::now()).to_std().unwrap_or_default();
 
-                        break;
+                    if control.sleep(wait).await.is_err() {
+                        return Err(Fired::Skipped);
                     }
-                    ExecType::Multiple(num) => {
-                        let driver = driver_arc_mutex.lock().await;
-                        let behavior = behavior_arc_mutex.lock().await;
-                        let db = db_arc_mutex.lock().await;
+                }
+            }
+        }
+    }
 
-                        self.action.clone().call(&driver, &behavior, &db).await?;
-                        times_ran += 1;
+    /// Runs the action once, through the same checks as a scheduled run.
+    pub(crate) async fn fire(
+        &self,
+        name: &str,
+        session_arc_mutex: &Mutex<SessionManager>,
+        config_arc_mutex: &Mutex<Config>,
+        db_arc_mutex: &Mutex<Database>,
+        control: &JobControl,
+    ) -> Fired {
+        let account = config_arc_mutex.lock().await.account.clone();
+
+        // Another job already found this account logged out; wait for the re-login.
+        if let Some(alert) = session_alert(account.as_str()).await {
+            raise_alert(account.as_str(), &alert.health, name).await;
+            control.pause();
+
+            return Fired::Skipped;
+        }
+
+        if let Err(fired) = self.within_quota(name, config_arc_mutex, db_arc_mutex, control).await {
+            return fired;
+        }
+
+        let disclosed = {
+            let config = config_arc_mutex.lock().await;
+
+            self.action.disclosed(&config.disclosure)
+        };
+
+        let disclosed = match disclosed {
+            Ok(disclosed) => disclosed,
+            Err(e) => {
+                tracing::error!(job = name, error = %e, "refusing to publish without disclosure");
+
+                return Fired::Dropped;
+            }
+        };
+
+        let review = {
+            let config = config_arc_mutex.lock().await;
+            let db = db_arc_mutex.lock().await;
+
+            config
+                .content_policy
+                .review_action(
+                    &db,
+                    config.account.as_str(),
+                    &disclosed,
+                    config.disclosure.label.as_str(),
+                )
+                .await
+        };
+
+        if let Some(review) = &review {
+            if review.is_rejected() && self.mode == ExecMode::Live {
+                let findings = review.summary();
+
+                tracing::warn!(job = name, findings, "content policy rejected, skipping run");
+
+                return Fired::Dropped;
+            }
+        }
+
+        let approved = self
+            .approved_action(name, disclosed, review, config_arc_mutex, db_arc_mutex, control)
+            .await;
+
+        let (action, pending_id) = match approved {
+            Some(approved) => approved,
+            None => {
+                tracing::info!(job = name, "action rejected, skipping this run");
+
+                return Fired::Dropped;
+            }
+        };
 
-                        drop(driver);
-                        drop(behavior);
-                        drop(db);
+        // The approval may have taken long enough for the quota to fill up meanwhile.
+        if pending_id.is_some() {
+            let allowed = self.within_quota(name, config_arc_mutex, db_arc_mutex, control).await;
 
-                        if times_ran == num {
-                            break;
+            if let Err(fired) = allowed {
+                return fired;
+            }
+        }
+
+        let mut session = session_arc_mutex.lock().await;
+
+        let driver = match session.driver().await {
+            Ok(driver) => driver,
+            Err(e) => {
+                tracing::error!(job = name, error = %e, "no webdriver session, skipping run");
+
+                return Fired::Skipped;
+            }
+        };
+
+        let config = config_arc_mutex.lock().await;
+        let db = db_arc_mutex.lock().await;
+
+        let (fired, _) = self.run_action(name, action, driver, &config, &db, control).await;
+
+        if let Some(id) = pending_id {
+            if let Err(e) = ApprovalQueue::new(&db).mark_executed(id.as_str()).await {
+                tracing::error!(job = name, error = %e, "could not mark approval executed");
+            }
+        }
+
+        drop(session);
+        drop(config);
+        drop(db);
+
+        fired
+    }
+
+    /// Checks the session, runs the action and writes the audit entry. A dry run also hands
+    /// back its report.
+    pub(crate) async fn run_action(
+        &self,
+        name: &str,
+        action: Action,
+        driver: &WebDriver,
+        config: &Config,
+        db: &Database,
+        control: &JobControl,
+    ) -> (Fired, Option<DryRunReport>) {
+        let mut log = StepLog::new(self.mode.clone());
+
+        let started_at = Utc::now();
+
+        let health = config.health.run(driver, config.account.as_str()).await;
+
+        // Only a bad session pauses the job; an unreachable page is tried again later.
+        if health.needs_relogin() && !log.is_dry_run() {
+            raise_alert(config.account.as_str(), &health, name).await;
+            control.pause();
+        }
+
+        let mut ran = false;
+
+        // Runs again here because an approver may have edited the text.
+        let (action, result) = match action.disclosed(&config.disclosure) {
+            Ok(disclosed) => {
+                let review = config
+                    .content_policy
+                    .review_action(
+                        db,
+                        config.account.as_str(),
+                        &disclosed,
+                        config.disclosure.label.as_str(),
+                    )
+                    .await;
+                let rejected = review.as_ref().filter(|x| x.is_rejected()).map(|x| x.summary());
+
+                if let Some(review) = review {
+                    log.set_review(review);
+                }
+
+                let result = match rejected {
+                    Some(summary) if !log.is_dry_run() => Err(bot_error(
+                        format!("content policy rejected the text: {}", summary),
+                    )),
+                    _ if !health.is_healthy() => Err(health.to_error()),
+                    _ => {
+                        if !log.is_dry_run() {
+                            let reserved =
+                                reserve_quota(name, config, db, &disclosed, started_at).await;
+
+                            if let Err(fired) = reserved {
+                                return (fired, None);
+                            }
                         }
+
+                        ran = true;
+
+                        disclosed
+                            .clone()
+                            .call(driver, &config.behavior, db, &mut log, control)
+                            .await
                     }
-                    ExecType::Forever => {
-                        let driver = driver_arc_mutex.lock().await;
-                        let behavior = behavior_arc_mutex.lock().await;
-                        let db = db_arc_mutex.lock().await;
+                };
+
+                if result.is_err() && ran && !log.is_dry_run() {
+                    let quotas = Quotas::new(db);
+                    let account = config.account.as_str();
+
+                    if let Err(e) = quotas.release(account, &disclosed, started_at).await {
+                        tracing::error!(job = name, error = %e, "could not release quota slot");
+                    }
+                }
+
+                if result.is_ok() && !log.is_dry_run() {
+                    let account = config.account.as_str();
+
+                    if let Some(text) = disclosed.content() {
+                        ContentPolicy::remember_posted(db, account, text.as_str()).await;
+                    }
+                }
+
+                (disclosed, result)
+            }
+            Err(e) => (action, Err(bot_error(e.to_string()))),
+        };
+
+        // Only failures inside the action's steps say anything about the page.
+        let artifacts = match &result {
+            Err(e) if !log.steps().is_empty() => {
+                let error = e.to_string();
+
+                config
+                    .failure_artifacts
+                    .collect(driver, name, action.kind(), log.steps().last(), error.as_str())
+                    .await
+            }
+            _ => None,
+        };
+
+        let result = result.map_err(|e| link_artifacts(e, artifacts.as_deref()));
+
+        if let Err(e) = &result {
+            tracing::error!(error = %e, "action failed after retries");
+        }
+
+        let outcome = match &result {
+            Ok(_) if log.is_dry_run() => AuditOutcome::DryRun,
+            Ok(_) => AuditOutcome::Done,
+            Err(e) => AuditOutcome::Failed(e.to_string()),
+        };
+
+        let label = action.is_publishing().then_some(config.disclosure.label.as_str());
+
+        if let Err(e) = AuditLog::new(db)
+            .append(
+                name,
+                config.account.as_str(),
+                &action,
+                started_at,
+                outcome.clone(),
+                label,
+                artifacts.as_deref(),
+            )
+            .await
+        {
+            tracing::error!(job = name, error = %e, "could not write audit entry");
+        }
+
+        let report = if log.is_dry_run() {
+            let report = log.report(action.kind(), result.err().map(|e| e.to_string()));
+
+            if let Err(e) = log.emit(&report) {
+                tracing::error!(error = %e, "could not write dry-run report");
+            }
+
+            Some(report)
+        } else {
+            None
+        };
+
+        let fired = match outcome {
+            _ if !ran && !health.is_healthy() => Fired::Skipped,
+            _ if !ran => Fired::Dropped,
+            outcome => Fired::Ran(outcome),
+        };
+
+        (fired, report)
+    }
+
+    pub async fn run_queue(
+        &self,
+        name: &str,
+        session_arc_mutex: Arc<Mutex<SessionManager>>,
+        config_arc_mutex: Arc<Mutex<Config>>,
+        db_arc_mutex: Arc<Mutex<Database>>,
+        receiver: &Receiver<u32>,
+        control: &JobControl,
+    ) -> WebDriverResult<()> {
+        // Jobs from the job store carry on from their recorded runs.
+        let stored = JobStore::new(&*db_arc_mutex.lock().await).get(name).await;
 
-                        self.action.clone().call(&driver, &behavior, &db).await?;
+        let (mut slots_used, mut last_run) = match stored {
+            Ok(Some(stored)) => (stored.slots_used(), stored.last_run_at),
+            Ok(None) => (0, None),
+            Err(e) => {
+                tracing::warn!(job = name, error = %e, "could not read job run state");
+
+                (0, None)
+            }
+        };
+
+        loop {
+            if control.is_cancelled() {
+                break;
+            }
 
-                        drop(driver);
-                        drop(behavior);
-                        drop(db);
+            let wait = match receiver.try_recv() {
+                Ok(0) => break,
+                Ok(u32_sent) => Duration::from_millis(u32_sent.into()),
+                Err(TryRecvError::Empty) => SCHEDULER_TICK,
+                Err(TryRecvError::Disconnected) => panic!("Bad cross-thread message"),
+            };
+
+            if control.sleep(wait).await.is_err() {
+                break;
+            }
+
+            let next = match self.next_fire(slots_used, last_run) {
+                Some(next) => next,
+                None => break,
+            };
+
+            let time_now = Utc::now();
+
+            if time_now >= next {
+                let fired = self
+                    .fire(name, &session_arc_mutex, &config_arc_mutex, &db_arc_mutex, control)
+                    .await;
+
+                if !matches!(fired, Fired::Skipped) {
+                    slots_used += 1;
+                    last_run = Some(time_now);
+
+                    let db = db_arc_mutex.lock().await;
+                    let store = JobStore::new(&db);
+
+                    // A dropped run is not counted as a run, but its slot is gone, so a
+                    // `Once` job whose run was dropped is finished.
+                    let recorded = match fired {
+                        Fired::Ran(_) => store.record_run(name, time_now).await,
+                        _ => store.record_drop(name, time_now).await,
+                    };
+
+                    if let Err(e) = recorded {
+                        tracing::warn!(job = name, error = %e, "could not record job run");
                     }
                 }
+
+                if !matches!(fired, Fired::Ran(_)) && control.sleep(SKIPPED_RETRY).await.is_err() {
+                    break;
+                }
             }
         }
 
         Ok(())
     }
 }
+
+/// Takes the quota slot for a live run right before it starts. `Err` says how the run
+/// ended when the quota filled up since `within_quota` looked.
+async fn reserve_quota(
+    name: &str,
+    config: &Config,
+    db: &Database,
+    action: &Action,
+    at: DateTime<Utc>,
+) -> Result<(), Fired> {
+    let account = config.account.as_str();
+
+    match Quotas::new(db).reserve(&config.quotas, account, action, at).await {
+        Ok(None) => Ok(()),
+        Ok(Some(exceeded)) => {
+            tracing::info!(job = name, reason = %exceeded, "over quota, not running");
+
+            match config.quotas.over_quota {
+                OverQuota::Drop => Err(Fired::Dropped),
+                OverQuota::Defer => Err(Fired::Skipped),
+            }
+        }
+        Err(e) => {
+            tracing::error!(job = name, error = %e, "could not reserve quota, skipping run");
+
+            Err(Fired::Skipped)
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::result::Result;
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum LabelPlacement {
+    Prefix,
+    Suffix,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct DisclosurePolicy {
+    pub label: String,
+    pub placement: LabelPlacement,
+    pub separator: String,
+    pub max_length: usize,
+}
+
+impl Default for DisclosurePolicy {
+    fn default() -> Self {
+        DisclosurePolicy {
+            label: String::from("[automated account]"),
+            placement: LabelPlacement::Suffix,
+            separator: String::from("\n\n"),
+            max_length: 280,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DisclosureError {
+    details: String,
+}
+
+impl fmt::Display for DisclosureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for DisclosureError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl DisclosureError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = DisclosureError {
+            details: details.to_string(),
+        };
+
+        Box::from(err)
+    }
+}
+
+impl DisclosurePolicy {
+    /// Adds the label to `text`. Text that already carries it is left as is, so this
+    /// can run again after an approver edits the action.
+    pub fn apply(&self, text: &str) -> Result<String, Box<DisclosureError>> {
+        if self.label.trim().is_empty() {
+            return Err(DisclosureError::new("disclosure label is empty"));
+        }
+
+        let labeled = if text.contains(self.label.as_str()) {
+            text.to_string()
+        } else if text.trim().is_empty() {
+            self.label.clone()
+        } else {
+            match self.placement {
+                LabelPlacement::Prefix => format!("{}{}{}", self.label, self.separator, text),
+                LabelPlacement::Suffix => format!("{}{}{}", text, self.separator, self.label),
+            }
+        };
+
+        let length = labeled.chars().count();
+
+        if length > self.max_length {
+            return Err(DisclosureError::new(
+                format!(
+                    "text is {} characters with the disclosure label, limit is {}",
+                    length, self.max_length
+                )
+                .as_str(),
+            ));
+        }
+
+        Ok(labeled)
+    }
+}
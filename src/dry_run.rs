@@ -0,0 +1,145 @@
+use crate::content_policy::ContentReview;
+use crate::utils::write_to_file;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Serialize, Clone, Deserialize, Default, Debug, PartialEq, Eq)]
+pub enum ExecMode {
+    #[default]
+    Live,
+    DryRun { report_dir: Option<String> },
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum StepKind {
+    Navigate,
+    Resolve,
+    Type,
+    Upload,
+    Click,
+    Search,
+    Record,
+    Capture,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct StepRecord {
+    pub kind: StepKind,
+    pub target: String,
+    pub text: Option<String>,
+    pub skipped: bool,
+    pub at: DateTime<Utc>,
+}
+
+/// Collects the steps an action takes, and tells the steps whether they may touch the site.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StepLog {
+    mode: ExecMode,
+    steps: Vec<StepRecord>,
+    review: Option<ContentReview>,
+}
+
+impl StepLog {
+    pub fn new(mode: ExecMode) -> Self {
+        StepLog {
+            mode,
+            steps: vec![],
+            review: None,
+        }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        matches!(self.mode, ExecMode::DryRun { .. })
+    }
+
+    /// Records a step and returns whether it should actually be performed. A search only
+    /// opens its results page in a dry run; scrolling through them is skipped.
+    pub fn record(&mut self, kind: StepKind, target: &str, text: Option<&str>) -> bool {
+        let skipped = self.is_dry_run()
+            && matches!(
+                kind,
+                StepKind::Type
+                    | StepKind::Upload
+                    | StepKind::Click
+                    | StepKind::Search
+                    | StepKind::Record
+                    | StepKind::Capture
+            );
+
+        self.push(kind, target, text, skipped);
+
+        !skipped
+    }
+
+    /// Records a step that cannot happen in this run, such as one on a dialog that only a
+    /// skipped click would have opened.
+    pub fn skip(&mut self, kind: StepKind, target: &str, text: Option<&str>) {
+        self.push(kind, target, text, true);
+    }
+
+    fn push(&mut self, kind: StepKind, target: &str, text: Option<&str>, skipped: bool) {
+        tracing::info!(?kind, target, skipped, "action step");
+
+        self.steps.push(StepRecord {
+            kind,
+            target: target.to_string(),
+            text: text.map(|x| x.to_string()),
+            skipped,
+            at: Utc::now(),
+        });
+    }
+
+    pub fn set_review(&mut self, review: ContentReview) {
+        self.review = Some(review);
+    }
+
+    pub fn steps(&self) -> &Vec<StepRecord> {
+        &self.steps
+    }
+
+    pub fn report(&self, action: &str, error: Option<String>) -> DryRunReport {
+        DryRunReport {
+            action: action.to_string(),
+            dry_run: self.is_dry_run(),
+            steps: self.steps.clone(),
+            content_review: self.review.clone(),
+            error,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Writes the report into the dry-run report directory, if one is configured.
+    pub fn emit(&self, report: &DryRunReport) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(report).unwrap();
+
+        tracing::info!(report = json.as_str(), "dry-run report");
+
+        if let ExecMode::DryRun {
+            report_dir: Some(dir),
+        } = &self.mode
+        {
+            std::fs::create_dir_all(dir)?;
+
+            let fname = format!(
+                "{}-{}.json",
+                report.created_at.format("%Y%m%dT%H%M%S%.3f"),
+                report.action
+            );
+
+            write_to_file(Path::new(dir).join(fname).to_str().unwrap(), json)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct DryRunReport {
+    pub action: String,
+    pub dry_run: bool,
+    pub steps: Vec<StepRecord>,
+    pub content_review: Option<ContentReview>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,219 @@
+use crate::dry_run::StepRecord;
+use crate::utils::{bot_error, write_bytes_atomic};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use thirtyfour::error::WebDriverError;
+use thirtyfour::prelude::*;
+
+const REPORT_FILE: &str = "failure.json";
+const SCREENSHOT_FILE: &str = "screenshot.png";
+const HTML_FILE: &str = "page.html";
+const CONSOLE_FILE: &str = "console.log";
+const DIR_TIME_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Keeps console messages in the page, since WebDriver has no standard way to read the
+/// browser log. Only messages logged after navigation are caught.
+const CONSOLE_HOOK_SCRIPT: &str = r#"
+    if (!window.__consoleLog) {
+        window.__consoleLog = [];
+        const keep = (level, args) => window.__consoleLog.push(
+            new Date().toISOString() + " " + level + " " + Array.from(args).join(" ")
+        );
+        for (const level of ["log", "info", "warn", "error"]) {
+            const original = console[level];
+            console[level] = function () {
+                keep(level, arguments);
+                return original.apply(console, arguments);
+            };
+        }
+        window.addEventListener("error", (e) => keep("uncaught", [e.message]));
+    }
+"#;
+const CONSOLE_READ_SCRIPT: &str = r#"return (window.__consoleLog || []).join("\n");"#;
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct FailureArtifacts {
+    pub enabled: bool,
+    pub dir: String,
+    /// Oldest failures are removed once there are more than this many.
+    pub max_kept: usize,
+    pub max_age_days: Option<i64>,
+}
+
+impl Default for FailureArtifacts {
+    fn default() -> Self {
+        FailureArtifacts {
+            enabled: true,
+            dir: String::from("failure-artifacts"),
+            max_kept: 50,
+            max_age_days: Some(14),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct FailureReport {
+    pub job: String,
+    pub action: String,
+    pub step: Option<StepRecord>,
+    pub error: String,
+    pub url: Option<String>,
+    pub failed_at: DateTime<Utc>,
+    /// Artifacts that could not be taken, usually because the session was gone.
+    pub missing: Vec<String>,
+}
+
+/// Starts keeping the console log of the page the driver is on.
+pub async fn watch_console(driver: &WebDriver) {
+    if let Err(e) = driver.execute_script(CONSOLE_HOOK_SCRIPT).await {
+        tracing::debug!(error = %e, "could not hook the console");
+    }
+}
+
+impl FailureArtifacts {
+    /// Saves the screenshot, page source, url and console log of the page `step` failed
+    /// on, and returns the directory they went to. Each artifact is taken on its own, so
+    /// a dead session still leaves a report.
+    pub async fn collect(
+        &self,
+        driver: &WebDriver,
+        job: &str,
+        action: &str,
+        step: Option<&StepRecord>,
+        error: &str,
+    ) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+
+        let failed_at = Utc::now();
+        let dir = Path::new(self.dir.as_str()).join(format!(
+            "{}-{}",
+            failed_at.format(DIR_TIME_FORMAT),
+            sanitize(job)
+        ));
+
+        if let Err(e) = fs::create_dir_all(&dir) {
+            tracing::error!(error = %e, "could not create failure artifact directory");
+
+            return None;
+        }
+
+        let mut missing = vec![];
+
+        let url = driver.current_url().await.ok().map(|x| x.to_string());
+
+        if url.is_none() {
+            missing.push(String::from("url"));
+        }
+
+        let artifacts = [
+            (SCREENSHOT_FILE, driver.screenshot_as_png().await.ok()),
+            (HTML_FILE, driver.page_source().await.ok().map(|x| x.into_bytes())),
+            (CONSOLE_FILE, console_log(driver).await.map(|x| x.into_bytes())),
+        ];
+
+        for (name, content) in artifacts {
+            let written = content.map(|x| write_bytes_atomic(path_in(&dir, name).as_str(), &x));
+
+            if !matches!(written, Some(Ok(_))) {
+                missing.push(name.to_string());
+            }
+        }
+
+        let report = FailureReport {
+            job: job.to_string(),
+            action: action.to_string(),
+            step: step.cloned(),
+            error: error.to_string(),
+            url,
+            failed_at,
+            missing,
+        };
+
+        let json = serde_json::to_string_pretty(&report).unwrap_or_default();
+
+        if let Err(e) = write_bytes_atomic(path_in(&dir, REPORT_FILE).as_str(), json.as_bytes()) {
+            tracing::error!(error = %e, "could not write failure report");
+        }
+
+        self.prune(failed_at);
+
+        let dir = dir.to_string_lossy().to_string();
+
+        tracing::warn!(job, dir = dir.as_str(), "saved failure artifacts");
+
+        Some(dir)
+    }
+
+    /// Removes failures past `max_age_days`, then the oldest beyond `max_kept`.
+    pub(crate) fn prune(&self, now: DateTime<Utc>) {
+        let entries = match fs::read_dir(self.dir.as_str()) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        let mut dirs: Vec<_> = entries
+            .filter_map(|x| x.ok())
+            .filter(|x| x.path().is_dir())
+            .map(|x| x.path())
+            .collect();
+
+        // Directory names start with the failure time, so they sort oldest first.
+        dirs.sort();
+
+        let cutoff = self.max_age_days.map(|x| now - Duration::days(x));
+        let excess = dirs.len().saturating_sub(self.max_kept);
+
+        for (i, dir) in dirs.iter().enumerate() {
+            let expired = match (cutoff, failed_at_of(dir)) {
+                (Some(cutoff), Some(failed_at)) => failed_at < cutoff,
+                _ => false,
+            };
+
+            if i < excess || expired {
+                if let Err(e) = fs::remove_dir_all(dir) {
+                    tracing::warn!(dir = ?dir, error = %e, "could not remove failure artifacts");
+                }
+            }
+        }
+    }
+}
+
+/// Appends where the artifacts went, so the error points at them.
+pub fn link_artifacts(error: WebDriverError, dir: Option<&str>) -> WebDriverError {
+    match dir {
+        Some(dir) => {
+            bot_error(format!("{} (failure artifacts in {})", error, dir))
+        }
+        None => error,
+    }
+}
+
+async fn console_log(driver: &WebDriver) -> Option<String> {
+    let ret = driver.execute_script(CONSOLE_READ_SCRIPT).await.ok()?;
+
+    ret.value().as_str().map(|x| x.to_string())
+}
+
+fn path_in(dir: &Path, name: &str) -> String {
+    dir.join(name).to_string_lossy().to_string()
+}
+
+pub(crate) fn failed_at_of(dir: &Path) -> Option<DateTime<Utc>> {
+    let name = dir.file_name()?.to_str()?;
+    let stamp = name.split('-').next()?;
+
+    chrono::NaiveDateTime::parse_from_str(stamp, DIR_TIME_FORMAT)
+        .ok()
+        .map(|x| DateTime::from_naive_utc_and_offset(x, Utc))
+}
+
+pub(crate) fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() || x == '_' { x } else { '_' })
+        .collect()
+}
@@ -0,0 +1,162 @@
+use crate::rate_limit::{limited_get, OpKind};
+use crate::utils::bot_error;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thirtyfour::error::WebDriverError;
+use thirtyfour::prelude::*;
+use tokio::sync::Mutex;
+
+const LOGIN_WALL_PATHS: [&str; 3] = ["/login", "/i/flow/login", "/account/access"];
+const LOGIN_WALL_XPATH: &str = r#"//*[@data-testid="loginButton" or @data-testid="login"]"#;
+const PROFILE_LINK_XPATH: &str = r#"//a[@data-testid="AppTabBar_Profile_Link"]"#;
+const ERROR_PAGE_XPATH: &str = r#"//*[@data-testid="error-detail"]"#;
+const ERROR_PAGE_TEXT: [&str; 2] = ["Something went wrong", "This page is down"];
+
+lazy_static! {
+    static ref ALERTS: Mutex<HashMap<String, SessionAlert>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct HealthCheck {
+    pub enabled: bool,
+    pub check_url: String,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck {
+            enabled: true,
+            check_url: String::from("https://twitter.com/home"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum SessionHealth {
+    Healthy,
+    LoginWall,
+    ErrorPage(String),
+    WrongAccount { expected: String, found: String },
+    Unreachable(String),
+}
+
+impl SessionHealth {
+    pub fn is_healthy(&self) -> bool {
+        *self == SessionHealth::Healthy
+    }
+
+    /// A login wall or someone else's account means the cookies have to be re-imported; an
+    /// unreachable page or an error page may clear up on its own.
+    pub fn needs_relogin(&self) -> bool {
+        matches!(self, SessionHealth::LoginWall | SessionHealth::WrongAccount { .. })
+    }
+
+    pub fn to_error(&self) -> WebDriverError {
+        if self.needs_relogin() {
+            bot_error(format!("session unusable, re-import cookies: {:?}", self))
+        } else {
+            bot_error(format!("session check failed: {:?}", self))
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct SessionAlert {
+    pub account: String,
+    pub health: SessionHealth,
+    pub raised_at: DateTime<Utc>,
+    pub paused_jobs: Vec<String>,
+}
+
+impl HealthCheck {
+    /// Loads `check_url` once and inspects what came back. Nothing here is retried: a
+    /// login wall will not go away by asking again.
+    pub async fn run(&self, driver: &WebDriver, account: &str) -> SessionHealth {
+        if !self.enabled {
+            return SessionHealth::Healthy;
+        }
+
+        if let Err(e) = limited_get(driver, self.check_url.clone(), OpKind::Record).await {
+            return SessionHealth::Unreachable(e.to_string());
+        }
+
+        match inspect(driver, account).await {
+            Ok(health) => health,
+            Err(e) => SessionHealth::Unreachable(e.to_string()),
+        }
+    }
+}
+
+async fn inspect(driver: &WebDriver, account: &str) -> WebDriverResult<SessionHealth> {
+    let url = driver.current_url().await?.to_string();
+
+    if LOGIN_WALL_PATHS.iter().any(|x| url.contains(x)) {
+        return Ok(SessionHealth::LoginWall);
+    }
+
+    if !driver.find_elements(By::XPath(LOGIN_WALL_XPATH)).await?.is_empty() {
+        return Ok(SessionHealth::LoginWall);
+    }
+
+    if !driver.find_elements(By::XPath(ERROR_PAGE_XPATH)).await?.is_empty() {
+        return Ok(SessionHealth::ErrorPage(url));
+    }
+
+    let source = driver.page_source().await?;
+
+    if let Some(text) = ERROR_PAGE_TEXT.iter().find(|x| source.contains(*x)) {
+        return Ok(SessionHealth::ErrorPage(text.to_string()));
+    }
+
+    let profile_links = driver.find_elements(By::XPath(PROFILE_LINK_XPATH)).await?;
+
+    let found = match profile_links.first() {
+        Some(link) => link.get_attribute("href").await?.unwrap_or_default(),
+        None => return Ok(SessionHealth::LoginWall),
+    };
+
+    let found = found.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    let expected = account.trim_start_matches('@');
+
+    if !expected.is_empty() && !found.eq_ignore_ascii_case(expected) {
+        return Ok(SessionHealth::WrongAccount {
+            expected: expected.to_string(),
+            found: found.to_string(),
+        });
+    }
+
+    Ok(SessionHealth::Healthy)
+}
+
+/// Records that `job` was paused because `account`'s session is unusable.
+pub async fn raise_alert(account: &str, health: &SessionHealth, job: &str) {
+    tracing::error!(account, job, health = ?health, "session unusable, re-import cookies");
+
+    let mut alerts = ALERTS.lock().await;
+
+    let alert = alerts.entry(account.to_string()).or_insert_with(|| SessionAlert {
+        account: account.to_string(),
+        health: health.clone(),
+        raised_at: Utc::now(),
+        paused_jobs: vec![],
+    });
+
+    if !alert.paused_jobs.iter().any(|x| x == job) {
+        alert.paused_jobs.push(job.to_string());
+    }
+}
+
+pub async fn session_alert(account: &str) -> Option<SessionAlert> {
+    ALERTS.lock().await.get(account).cloned()
+}
+
+pub async fn session_alerts() -> Vec<SessionAlert> {
+    ALERTS.lock().await.values().cloned().collect()
+}
+
+/// Drops the alert once the cookies were re-imported, handing back the jobs to resume.
+pub async fn clear_alert(account: &str) -> Option<SessionAlert> {
+    ALERTS.lock().await.remove(account)
+}
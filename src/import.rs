@@ -0,0 +1,303 @@
+use crate::rate_limit::{limited_send, OpKind};
+use crate::record_posts::{PostRef, SearchHeader};
+use crate::retry::{with_retry, RetryPolicy};
+use crate::utils::{make_get_post_detail_url, read_from_file, write_to_file_atomic};
+use futures::future::join_all;
+use mongodb::bson::{doc, to_bson, Bson, Document};
+use mongodb::options::UpdateOptions;
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, Value};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::result::Result;
+use tokio::time::{sleep, Duration};
+
+const IMPORT_COLL_NAME: &str = "imported-posts";
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum LinkFormat {
+    Newline,
+    Csv,
+    Json,
+}
+
+impl LinkFormat {
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|x| x.to_str()) {
+            Some("csv") => LinkFormat::Csv,
+            Some("json") => LinkFormat::Json,
+            _ => LinkFormat::Newline,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ImportError {
+    details: String,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for ImportError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl ImportError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = ImportError {
+            details: details.to_string(),
+        };
+
+        Box::from(err)
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct ImportProgress {
+    pub source: String,
+    pub total: usize,
+    pub next_index: usize,
+    pub failed: Vec<String>,
+}
+
+impl ImportProgress {
+    fn load(progress_path: &str, source: &str, total: usize) -> Self {
+        let fresh = ImportProgress {
+            source: source.to_string(),
+            total,
+            next_index: 0,
+            failed: vec![],
+        };
+
+        match read_from_file(progress_path) {
+            Ok(contents) => match from_str::<ImportProgress>(contents.as_str()) {
+                Ok(progress) if progress.source == source && progress.total == total => progress,
+                _ => fresh,
+            },
+            Err(_) => fresh,
+        }
+    }
+
+    fn save(&self, progress_path: &str) -> std::io::Result<()> {
+        write_to_file_atomic(progress_path, serde_json::to_string_pretty(self).unwrap())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next_index >= self.total
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct LinkImport {
+    path: String,
+    format: Option<LinkFormat>,
+    progress_path: Option<String>,
+    link_id: String,
+    search_header: SearchHeader,
+    concurrency: usize,
+    min_interval_ms: u64,
+    max_per_session: Option<usize>,
+    #[serde(default)]
+    retry: RetryPolicy,
+}
+
+impl LinkImport {
+    pub fn from_json_str(json_str: String) -> Result<Self, Box<ImportError>> {
+        from_str(json_str.as_str()).map_err(|e| ImportError::new(e.to_string().as_str()))
+    }
+
+    fn progress_path(&self) -> String {
+        match self.progress_path.clone() {
+            Some(path) => path,
+            None => format!("{}.progress.json", self.path),
+        }
+    }
+
+    pub fn parse_links(contents: &str, format: &LinkFormat) -> Vec<PostRef> {
+        let raw: Vec<String> = match format {
+            LinkFormat::Newline => contents
+                .lines()
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty() && !x.starts_with("#"))
+                .map(|x| x.to_string())
+                .collect(),
+            LinkFormat::Csv => contents
+                .lines()
+                .filter_map(|line| {
+                    line.split(",")
+                        .map(|x| x.trim().trim_matches('"'))
+                        .find(|x| x.contains("/status/"))
+                        .map(|x| x.to_string())
+                })
+                .collect(),
+            LinkFormat::Json => match from_str::<Value>(contents) {
+                Ok(Value::Array(items)) => items
+                    .into_iter()
+                    .filter_map(|item| match item {
+                        Value::String(s) => Some(s),
+                        Value::Object(map) => ["url", "link", "href"]
+                            .iter()
+                            .find_map(|k| map.get(*k).and_then(|v| v.as_str()))
+                            .map(|x| x.to_string()),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            },
+        };
+
+        let mut seen = HashSet::<String>::new();
+
+        raw.iter()
+            .filter_map(|x| PostRef::from_url(x))
+            .filter(|x| seen.insert(x.post_id.clone()))
+            .collect()
+    }
+
+    async fn fetch_details(&self, post: &PostRef) -> Result<Value, Box<ImportError>> {
+        let url = make_get_post_detail_url(post.post_id.clone(), self.link_id.clone(), None);
+
+        let client = reqwest::Client::new();
+
+        let res = with_retry(&self.retry, post.url().as_str(), || {
+            limited_send(
+                self.search_header.attach(client.get(url.clone())),
+                url.as_str(),
+                OpKind::Api,
+            )
+        })
+        .await
+        .map_err(|e| ImportError::new(e.to_string().as_str()))?;
+
+        if !res.status().is_success() {
+            return Err(ImportError::new(
+                format!("{} returned {}", post.url(), res.status()).as_str(),
+            ));
+        }
+
+        let text = res
+            .text()
+            .await
+            .map_err(|e| ImportError::new(e.to_string().as_str()))?;
+
+        from_str::<Value>(text.as_str()).map_err(|e| ImportError::new(e.to_string().as_str()))
+    }
+
+    async fn store_details(
+        db: &Database,
+        post: &PostRef,
+        details: Value,
+    ) -> Result<(), Box<ImportError>> {
+        let collection = db.collection::<Document>(IMPORT_COLL_NAME);
+
+        let details_bson = to_bson(&details).unwrap_or(Bson::Null);
+
+        collection
+            .update_one(
+                doc! {"post": post.post_id.clone()},
+                doc! {"$set": {
+                    "username": post.username.clone(),
+                    "post": post.post_id.clone(),
+                    "details": details_bson,
+                }},
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await
+            .map_err(|e| ImportError::new(e.to_string().as_str()))?;
+
+        Ok(())
+    }
+
+    /// Fetches and stores one chunk concurrently, returning the urls that failed.
+    async fn import_chunk(&self, db: &Database, chunk: &[PostRef]) -> Vec<String> {
+        let results = join_all(chunk.iter().map(|post| async move {
+            let details = self.fetch_details(post).await?;
+
+            Self::store_details(db, post, details).await
+        }))
+        .await;
+
+        chunk
+            .iter()
+            .zip(results)
+            .filter(|(_, result)| result.is_err())
+            .map(|(post, _)| post.url())
+            .collect()
+    }
+
+    /// Runs one session of the import, resuming from the saved progress marker. Links that
+    /// failed in earlier sessions are tried again first, once per session, and stay in
+    /// `failed` until they go through.
+    pub async fn run(&self, db: &Database) -> Result<ImportProgress, Box<ImportError>> {
+        let contents = read_from_file(self.path.as_str())
+            .map_err(|e| ImportError::new(e.to_string().as_str()))?;
+
+        let format = match self.format.clone() {
+            Some(format) => format,
+            None => LinkFormat::from_path(self.path.as_str()),
+        };
+
+        let posts = Self::parse_links(contents.as_str(), &format);
+        let progress_path = self.progress_path();
+
+        let mut progress = ImportProgress::load(&progress_path, &self.path, posts.len());
+
+        let mut budget = self.max_per_session.unwrap_or(usize::MAX);
+        let chunk_size = self.concurrency.max(1);
+
+        let retry: Vec<PostRef> = progress
+            .failed
+            .iter()
+            .filter_map(|x| PostRef::from_url(x.as_str()))
+            .collect();
+
+        for chunk in retry.chunks(chunk_size) {
+            let chunk = &chunk[..chunk.len().min(budget)];
+
+            if chunk.is_empty() {
+                break;
+            }
+
+            let failed = self.import_chunk(db, chunk).await;
+
+            progress
+                .failed
+                .retain(|x| failed.contains(x) || !chunk.iter().any(|post| post.url() == *x));
+            budget -= chunk.len();
+
+            progress
+                .save(&progress_path)
+                .map_err(|e| ImportError::new(e.to_string().as_str()))?;
+
+            sleep(Duration::from_millis(self.min_interval_ms)).await;
+        }
+
+        let session_end = progress.next_index.saturating_add(budget).min(posts.len());
+
+        while progress.next_index < session_end {
+            let chunk_end = (progress.next_index + chunk_size).min(session_end);
+            let chunk = &posts[progress.next_index..chunk_end];
+
+            let failed = self.import_chunk(db, chunk).await;
+
+            progress.failed.extend(failed);
+            progress.next_index = chunk_end;
+            progress
+                .save(&progress_path)
+                .map_err(|e| ImportError::new(e.to_string().as_str()))?;
+
+            sleep(Duration::from_millis(self.min_interval_ms)).await;
+        }
+
+        Ok(progress)
+    }
+}
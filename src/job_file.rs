@@ -0,0 +1,378 @@
+use crate::action::Action;
+use crate::cronueue::{CronueueAction, ExecType};
+use crate::dry_run::ExecMode;
+use crate::jobs::{JobError, JobStore};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs::read_to_string;
+use std::ops::Range;
+use std::path::Path;
+use std::result::Result;
+
+/// Jobs written down instead of built in code, as TOML (`[[jobs]]` tables) or YAML (a
+/// `jobs:` list), picked by the file extension:
+///
+/// ```toml
+/// [[jobs]]
+/// name = "morning-post"
+/// start = "2026-10-20T08:00:00Z"
+/// every = "1d"
+/// limits = { max_runs = 30 }
+///
+/// [jobs.action.PostText]
+/// url = "https://twitter.com/compose/tweet"
+/// content = "Good morning"
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct JobFileDoc {
+    #[serde(default)]
+    jobs: Vec<JobEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct JobEntry {
+    name: String,
+    /// RFC 3339, quoted in TOML.
+    start: DateTime<Utc>,
+    /// A number with a unit: `90s`, `15m`, `2h` or `1d`. Without it the job runs once.
+    #[serde(default)]
+    every: Option<String>,
+    action: Action,
+    #[serde(default)]
+    limits: Limits,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct Limits {
+    /// Runs before the job is done. Without it a job with `every` runs until removed.
+    #[serde(default)]
+    max_runs: Option<u32>,
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    report_dir: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobDefinition {
+    pub name: String,
+    pub job: CronueueAction,
+}
+
+/// What applying a jobs file changed in the job store.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct JobFileChanges {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl JobFileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Every problem in the file, one `path:line: message` per line.
+#[derive(Debug)]
+pub struct JobFileError {
+    details: String,
+}
+
+impl fmt::Display for JobFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for JobFileError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl JobFileError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = JobFileError {
+            details: details.to_string(),
+        };
+
+        Box::from(err)
+    }
+
+    pub fn problems(&self) -> Vec<String> {
+        self.details.lines().map(|x| x.to_string()).collect()
+    }
+}
+
+impl From<Box<JobError>> for Box<JobFileError> {
+    fn from(e: Box<JobError>) -> Self {
+        JobFileError::new(e.to_string().as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Toml,
+    Yaml,
+}
+
+pub struct JobFile {
+    path: String,
+}
+
+impl JobFile {
+    pub fn new(path: &str) -> Self {
+        JobFile {
+            path: path.to_string(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.as_str()
+    }
+
+    /// Reads and checks the file. Nothing is returned unless every entry is valid, so a
+    /// half-edited file never removes jobs.
+    pub fn load(&self) -> Result<Vec<JobDefinition>, Box<JobFileError>> {
+        let source = read_to_string(self.path.as_str())
+            .map_err(|e| JobFileError::new(format!("{}: {}", self.path, e).as_str()))?;
+
+        self.parse(source.as_str())
+    }
+
+    pub fn parse(&self, source: &str) -> Result<Vec<JobDefinition>, Box<JobFileError>> {
+        let doc: JobFileDoc = match self.format()? {
+            Format::Toml => toml::from_str(source).map_err(|e| {
+                let line = e.span().map(|x| line_at(source, x.start));
+
+                self.error(line, e.message())
+            })?,
+            Format::Yaml => serde_yaml::from_str(source).map_err(|e| {
+                let line = e.location().map(|x| x.line());
+
+                self.error(line, e.to_string().as_str())
+            })?,
+        };
+
+        let mut problems = vec![];
+        let mut names = HashSet::new();
+        let mut defs = vec![];
+        let starts = entry_starts(source, self.format()?);
+
+        for (i, entry) in doc.jobs.into_iter().enumerate() {
+            let lines = starts.get(i).map(|x| *x..starts.get(i + 1).copied().unwrap_or(usize::MAX));
+            let line_of = |key: &str| lines.clone().and_then(|x| line_of_key(source, x, key));
+
+            if entry.name.trim().is_empty() {
+                problems.push(self.problem(line_of("name"), "name is empty"));
+
+                continue;
+            }
+
+            if !names.insert(entry.name.clone()) {
+                let message = format!("job {} is defined twice", entry.name);
+
+                problems.push(self.problem(line_of("name"), &message));
+
+                continue;
+            }
+
+            match definition(entry) {
+                Ok(def) => defs.push(def),
+                Err((key, message)) => problems.push(self.problem(line_of(key), &message)),
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(JobFileError::new(problems.join("\n").as_str()));
+        }
+
+        Ok(defs)
+    }
+
+    /// Makes the job store match the file. Only jobs whose definition changed are
+    /// replaced, and only jobs that came from this file are removed. Paused jobs stay
+    /// paused.
+    pub async fn apply(
+        &self,
+        store: &JobStore,
+        defs: &[JobDefinition],
+    ) -> Result<JobFileChanges, Box<JobFileError>> {
+        let stored = store.list().await?;
+        let mut changes = JobFileChanges::default();
+
+        for def in defs {
+            match stored.iter().find(|x| x.name == def.name) {
+                Some(existing) if existing.source.as_deref() != Some(self.path()) => {
+                    tracing::warn!(
+                        job = def.name.as_str(),
+                        file = self.path(),
+                        "a job with this name was added by hand, leaving it alone"
+                    );
+                }
+                Some(existing) if existing.job == def.job => {}
+                Some(_) => {
+                    store.define(def.name.as_str(), def.job.clone(), self.path()).await?;
+                    changes.changed.push(def.name.clone());
+                }
+                None => {
+                    store.define(def.name.as_str(), def.job.clone(), self.path()).await?;
+                    changes.added.push(def.name.clone());
+                }
+            }
+        }
+
+        for job in stored.iter() {
+            let from_here = job.source.as_deref() == Some(self.path());
+
+            if from_here && !defs.iter().any(|x| x.name == job.name) {
+                store.remove(job.name.as_str()).await?;
+                changes.removed.push(job.name.clone());
+            }
+        }
+
+        Ok(changes)
+    }
+
+    fn format(&self) -> Result<Format, Box<JobFileError>> {
+        match Path::new(self.path.as_str()).extension().and_then(|x| x.to_str()) {
+            Some("toml") => Ok(Format::Toml),
+            Some("yaml") | Some("yml") => Ok(Format::Yaml),
+            _ => Err(self.error(None, "jobs file must end in .toml, .yaml or .yml")),
+        }
+    }
+
+    fn problem(&self, line: Option<usize>, message: &str) -> String {
+        match line {
+            Some(line) => format!("{}:{}: {}", self.path, line, message),
+            None => format!("{}: {}", self.path, message),
+        }
+    }
+
+    fn error(&self, line: Option<usize>, message: &str) -> Box<JobFileError> {
+        JobFileError::new(self.problem(line, message.trim()).as_str())
+    }
+}
+
+/// A bad entry fails with the key to point at and what is wrong with it.
+fn definition(entry: JobEntry) -> Result<JobDefinition, (&'static str, String)> {
+    let every = match entry.every.as_deref() {
+        Some(every) => Some(parse_every(every).map_err(|e| ("every", e))?),
+        None => None,
+    };
+
+    let exec_type = match (every, entry.limits.max_runs) {
+        (_, Some(0)) => {
+            return Err(("max_runs", String::from("limits.max_runs must be at least 1")));
+        }
+        (None, Some(runs)) if runs > 1 => {
+            return Err(("max_runs", String::from("limits.max_runs above 1 needs `every`")));
+        }
+        (None, _) | (Some(_), Some(1)) => ExecType::Once,
+        (Some(_), Some(runs)) => ExecType::Multiple(runs),
+        (Some(_), None) => ExecType::Forever,
+    };
+
+    let mode = if entry.limits.dry_run {
+        ExecMode::DryRun {
+            report_dir: entry.limits.report_dir,
+        }
+    } else if entry.limits.report_dir.is_some() {
+        return Err(("report_dir", String::from("limits.report_dir only applies with dry_run")));
+    } else {
+        ExecMode::Live
+    };
+
+    let mut job = CronueueAction::new(entry.start, entry.action, exec_type).with_mode(mode);
+
+    if let Some(every) = every {
+        job = job.with_every(every);
+    }
+
+    Ok(JobDefinition {
+        name: entry.name,
+        job,
+    })
+}
+
+/// `every` in seconds.
+fn parse_every(every: &str) -> Result<u64, String> {
+    let every = every.trim();
+    let split = every.find(|x: char| !x.is_ascii_digit()).unwrap_or(every.len());
+    let (num, unit) = every.split_at(split);
+
+    let num: u64 = num
+        .parse()
+        .map_err(|_| format!("every {:?} does not start with a number", every))?;
+
+    let unit_secs = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return Err(format!("every {:?} needs a unit of s, m, h or d", every)),
+    };
+
+    let secs = num
+        .checked_mul(unit_secs)
+        .filter(|x| *x <= i64::MAX as u64)
+        .ok_or_else(|| format!("every {:?} is too long", every))?;
+
+    if secs == 0 {
+        return Err(String::from("every must be longer than 0s"));
+    }
+
+    Ok(secs)
+}
+
+fn line_at(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+/// The first line of every job entry, in file order: `[[jobs]]` headers in TOML, list
+/// items under `jobs:` in YAML. Empty when the jobs are written inline.
+fn entry_starts(source: &str, format: Format) -> Vec<usize> {
+    let lines = source.lines().enumerate();
+
+    match format {
+        Format::Toml => lines.filter(|(_, x)| x.trim() == "[[jobs]]").map(|(i, _)| i + 1).collect(),
+        Format::Yaml => {
+            let items: Vec<(usize, usize)> = lines
+                .filter(|(_, x)| x.trim_start().starts_with("- "))
+                .map(|(i, x)| (i + 1, x.len() - x.trim_start().len()))
+                .collect();
+            let indent = items.iter().map(|(_, x)| *x).min();
+
+            items.into_iter().filter(|(_, x)| Some(*x) == indent).map(|(i, _)| i).collect()
+        }
+    }
+}
+
+/// The line within `lines` that sets `key`, in either format and in inline tables too.
+/// Serde keeps no positions past parsing, so problems are traced back to the key by text.
+fn line_of_key(source: &str, lines: Range<usize>, key: &str) -> Option<usize> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(i, x)| (i + 1, x))
+        .filter(|(i, _)| lines.contains(i))
+        .find(|(_, x)| sets_key(x, key))
+        .map(|(i, _)| i)
+}
+
+fn sets_key(line: &str, key: &str) -> bool {
+    line.match_indices(key).any(|(at, _)| {
+        let before = line[..at].chars().next_back();
+        let after = line[at + key.len()..].trim_start().chars().next();
+
+        !before.is_some_and(|x| x.is_alphanumeric() || x == '_' || x == '.')
+            && matches!(after, Some('=') | Some(':'))
+    })
+}
@@ -0,0 +1,222 @@
+use crate::cronueue::CronueueAction;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::result::Result;
+
+const JOBS_COLL_NAME: &str = "jobs";
+
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "http-api", derive(utoipa::ToSchema))]
+pub enum JobStatus {
+    Active,
+    Paused,
+}
+
+/// A job as the daemon should run it. Removing the document stops the job.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct StoredJob {
+    pub name: String,
+    pub job: CronueueAction,
+    pub status: JobStatus,
+    #[serde(default)]
+    pub times_ran: u32,
+    /// Runs dropped by the quota or the content policy. They use up their slot.
+    #[serde(default)]
+    pub times_dropped: u32,
+    #[serde(default)]
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+    /// The jobs file this job is defined in. `None` for jobs added by hand.
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+impl StoredJob {
+    pub fn slots_used(&self) -> u32 {
+        self.times_ran.saturating_add(self.times_dropped)
+    }
+
+    pub fn next_fire(&self) -> Option<DateTime<Utc>> {
+        self.job.next_fire(self.slots_used(), self.last_run_at)
+    }
+}
+
+#[derive(Debug)]
+pub struct JobError {
+    details: String,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for JobError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl JobError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = JobError {
+            details: details.to_string(),
+        };
+
+        Box::from(err)
+    }
+}
+
+impl From<mongodb::error::Error> for Box<JobError> {
+    fn from(e: mongodb::error::Error) -> Self {
+        JobError::new(e.to_string().as_str())
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for Box<JobError> {
+    fn from(e: mongodb::bson::ser::Error) -> Self {
+        JobError::new(e.to_string().as_str())
+    }
+}
+
+pub struct JobStore {
+    jobs: Collection<StoredJob>,
+}
+
+impl JobStore {
+    pub fn new(db: &Database) -> Self {
+        JobStore {
+            jobs: db.collection::<StoredJob>(JOBS_COLL_NAME),
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<StoredJob>, Box<JobError>> {
+        let options = FindOptions::builder().sort(doc! {"name": 1}).build();
+
+        let cursor = self.jobs.find(None, options).await?;
+        let jobs: Vec<StoredJob> = cursor.try_collect().await?;
+
+        Ok(jobs)
+    }
+
+    pub async fn get(&self, name: &str) -> Result<Option<StoredJob>, Box<JobError>> {
+        let job = self.jobs.find_one(doc! {"name": name}, None).await?;
+
+        Ok(job)
+    }
+
+    pub async fn add(&self, name: &str, job: CronueueAction) -> Result<StoredJob, Box<JobError>> {
+        if self.get(name).await?.is_some() {
+            return Err(JobError::new(format!("job {} already exists", name).as_str()));
+        }
+
+        let stored = StoredJob {
+            name: name.to_string(),
+            job,
+            status: JobStatus::Active,
+            times_ran: 0,
+            times_dropped: 0,
+            last_run_at: None,
+            updated_at: Utc::now(),
+            source: None,
+        };
+
+        self.jobs.insert_one(&stored, None).await?;
+
+        Ok(stored)
+    }
+
+    /// Adds or replaces a job from a jobs file. A replaced job keeps its status but starts
+    /// counting runs again, since the old count belongs to the old schedule.
+    pub async fn define(
+        &self,
+        name: &str,
+        job: CronueueAction,
+        source: &str,
+    ) -> Result<(), Box<JobError>> {
+        if self.get(name).await?.is_none() {
+            let stored = StoredJob {
+                name: name.to_string(),
+                job,
+                status: JobStatus::Active,
+                times_ran: 0,
+                times_dropped: 0,
+                last_run_at: None,
+                updated_at: Utc::now(),
+                source: Some(source.to_string()),
+            };
+
+            self.jobs.insert_one(&stored, None).await?;
+
+            return Ok(());
+        }
+
+        let update = doc! {"$set": {
+            "job": mongodb::bson::to_bson(&job)?,
+            "times_ran": 0,
+            "times_dropped": 0,
+            "last_run_at": mongodb::bson::Bson::Null,
+            "updated_at": mongodb::bson::to_bson(&Utc::now())?,
+            "source": source,
+        }};
+
+        self.jobs.update_one(doc! {"name": name}, update, None).await?;
+
+        Ok(())
+    }
+
+    pub async fn set_status(&self, name: &str, status: JobStatus) -> Result<(), Box<JobError>> {
+        let update = doc! {"$set": {
+            "status": mongodb::bson::to_bson(&status)?,
+            "updated_at": mongodb::bson::to_bson(&Utc::now())?,
+        }};
+
+        let result = self.jobs.update_one(doc! {"name": name}, update, None).await?;
+
+        if result.matched_count == 0 {
+            return Err(JobError::new(format!("no job named {}", name).as_str()));
+        }
+
+        Ok(())
+    }
+
+    pub async fn remove(&self, name: &str) -> Result<(), Box<JobError>> {
+        let result = self.jobs.delete_one(doc! {"name": name}, None).await?;
+
+        if result.deleted_count == 0 {
+            return Err(JobError::new(format!("no job named {}", name).as_str()));
+        }
+
+        Ok(())
+    }
+
+    /// Counts a run, so a restarted daemon does not fire the same slot twice.
+    pub async fn record_run(&self, name: &str, at: DateTime<Utc>) -> Result<(), Box<JobError>> {
+        let update = doc! {
+            "$inc": {"times_ran": 1},
+            "$set": {"last_run_at": mongodb::bson::to_bson(&at)?},
+        };
+
+        self.jobs.update_one(doc! {"name": name}, update, None).await?;
+
+        Ok(())
+    }
+
+    pub async fn record_drop(&self, name: &str, at: DateTime<Utc>) -> Result<(), Box<JobError>> {
+        let update = doc! {
+            "$inc": {"times_dropped": 1},
+            "$set": {"last_run_at": mongodb::bson::to_bson(&at)?},
+        };
+
+        self.jobs.update_one(doc! {"name": name}, update, None).await?;
+
+        Ok(())
+    }
+}
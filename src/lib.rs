@@ -4,22 +4,90 @@
 extern crate lazy_static;
 
 mod action;
+#[cfg(feature = "http-api")]
+mod api;
+mod approval;
+mod audit;
 mod bot;
+mod cancel;
+mod capture;
 mod config;
+mod content_policy;
 mod cookie;
 mod cronueue;
+mod disclosure;
+mod dry_run;
+mod failure;
+mod health;
+mod import;
+mod job_file;
+mod jobs;
+mod media;
 mod proxy;
+mod quota;
+mod rate_limit;
 mod record_posts;
 mod search;
+mod session;
+mod thread;
 mod utils;
 mod read_write_queue;
+mod retry;
+
+pub use crate::action::Action;
+#[cfg(feature = "http-api")]
+pub use crate::api::{openapi_json, ApiConfig};
+pub use crate::approval::{ApprovalQueue, ApprovalStatus, PendingAction};
+pub use crate::audit::AuditOutcome;
+pub use crate::bot::Bot;
+pub use crate::config::Config;
+pub use crate::cronueue::{CronueueAction, ExecType};
+pub use crate::dry_run::{DryRunReport, ExecMode};
+pub use crate::job_file::{JobDefinition, JobFile, JobFileChanges, JobFileError};
+pub use crate::jobs::{JobError, JobStatus, JobStore, StoredJob};
+pub use crate::record_posts::{recorded_posts, RecordedPost};
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "http-api")]
+    use crate::api::{openapi_json, router};
+    use crate::approval::{ApprovalDecision, ApprovalStatus, Decision, PendingAction};
+    use crate::audit::{AuditEntry, AuditOutcome, ChainCheck};
+    use crate::cancel::JobControl;
+    use crate::capture::{
+        record_id, refetch_fields, sha256_hex, warc_records, write_manifest, Capture,
+        CaptureManifest, CapturedFile,
+    };
+    use crate::content_policy::{CheckKind, ContentPolicy, Severity};
     use crate::cookie;
-    use crate::utils::write_to_file;
+    use crate::cronueue::{CronueueAction, ExecType, Fired};
+    use crate::disclosure::DisclosurePolicy;
+    use crate::dry_run::{ExecMode, StepKind, StepLog};
+    use crate::failure::{failed_at_of, link_artifacts, sanitize, FailureArtifacts};
+    use crate::health::{HealthCheck, SessionHealth};
+    use crate::import::{LinkFormat, LinkImport};
+    use crate::job_file::JobFile;
+    use crate::jobs::{JobStatus, StoredJob};
+    use crate::media::{dir_size, mime_of, MediaArchive, MediaKind, MediaRef};
+    use crate::rate_limit::{
+        throttle_wait, BucketLimit, OpKind, RateLimitConfig, RateLimiter, RateRule,
+    };
+    use crate::quota::{OverQuota, QuotaConfig, QuotaKind, QuotaLimit, QuotaWindow};
+    use crate::record_posts::{
+        bottom_cursor, parse_article, tab_url, timeline_posts, Inclusion, PostRef,
+        RecordSelector, TimelinePost, TweetType,
+    };
+    use crate::retry::{click_not_delivered, with_retry, with_retry_if, RetryPolicy, Retryable};
+    use crate::thread::{build_tree, place_posts, thread_cursors, ThreadPost};
+    use crate::utils::{bot_error, write_to_file, write_to_file_atomic};
+    use imagesize::ImageType;
     use std::default::Default;
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
     use std::fs::remove_file;
+    use std::path::Path;
+    use std::time::Duration;
+    use thirtyfour::error::{no_such_element, WebDriverError};
+    use tokio_util::sync::CancellationToken;
 
     #[test]
     fn test_cookies_str() {
@@ -98,4 +166,1143 @@ mod tests {
 
         remove_file("./temp.json").unwrap();
     }
+
+    #[test]
+    fn test_cookies_try_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.json");
+        let path = path.to_str().unwrap();
+
+        write_to_file(path, String::from(r#"[{"name": "a", "value": "b"}]"#)).unwrap();
+        assert_eq!(cookie::Cookie::try_from_file(path).unwrap().len(), 1);
+
+        write_to_file(path, String::from("[{\"name\": ")).unwrap();
+        assert!(cookie::Cookie::try_from_file(path).is_err());
+
+        let missing = dir.path().join("missing.json");
+        assert!(cookie::Cookie::try_from_file(missing.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_cookie_import_formats() {
+        let netscape = "# Netscape HTTP Cookie File\n\
+            #HttpOnly_.twitter.com\tTRUE\t/\tTRUE\t1\tauth_token\tabc\n\
+            .twitter.com\tTRUE\t/\tFALSE\t0\tlang\ten\n";
+
+        let cookies = cookie::Cookie::from_netscape(netscape).unwrap();
+
+        assert_eq!(cookies.len(), 2);
+        assert_eq!(cookies[0].httpOnly, Some(true));
+        assert_eq!(cookies[0].secure, Some(true));
+        assert_eq!(cookies[1].expires, None);
+        assert_eq!(cookie::Cookie::warn_expired(&cookies, chrono::Utc::now()), 1);
+
+        let exported = r#"[{
+            "name": "ct0",
+            "value": "xyz",
+            "domain": ".twitter.com",
+            "httpOnly": false,
+            "sameSite": "no_restriction",
+            "expirationDate": 4102444800.5
+        }]"#;
+
+        let cookies = cookie::Cookie::from_json_export(exported).unwrap();
+
+        assert_eq!(cookies[0].sameSite, Some(String::from("None")));
+        assert_eq!(cookies[0].expires, Some(4102444800));
+
+        let wire = cookies[0].to_wire().unwrap();
+
+        assert_eq!(wire["httpOnly"], serde_json::json!(false));
+        assert_eq!(wire["expiry"], serde_json::json!(4102444800i64));
+        assert_eq!(wire["domain"], serde_json::json!(".twitter.com"));
+        assert!(wire.get("path").is_none());
+
+        let bad = cookie::Cookie {
+            sameSite: Some(String::from("unspecified")),
+            ..cookies[0].clone()
+        };
+
+        assert!(bad.to_wire().is_err());
+    }
+
+    #[test]
+    fn test_cookies_group_by_domain() {
+        let cookie = |name: &str, domain: Option<&str>| cookie::Cookie {
+            name: String::from(name),
+            domain: domain.map(String::from),
+            ..Default::default()
+        };
+
+        let groups = cookie::Cookie::group_by_domain(vec![
+            cookie("a", Some(".twitter.com")),
+            cookie("b", Some("x.com")),
+            cookie("c", Some("twitter.com")),
+            cookie("d", None),
+        ]);
+
+        let hosts: Vec<&str> = groups.iter().map(|(x, _)| x.as_str()).collect();
+
+        assert_eq!(hosts, vec!["twitter.com", "x.com", ""]);
+        assert_eq!(groups[0].1.len(), 2);
+    }
+
+    #[test]
+    fn test_import_links_dedupe() {
+        let links = r#"
+            /MrsSudoku/status/1508408582982799360
+            /MrsSudoku/status/1508408582982799360/photo/1
+            https://twitter.com/raoulmarks/status/1508829648121380870?s=20
+            # a comment
+            /motionpunk1/likes
+        "#;
+
+        let is_and_is = LinkImport::parse_links(links, &LinkFormat::Newline);
+
+        let should_be = vec![
+            PostRef::new(
+                String::from("MrsSudoku"),
+                String::from("1508408582982799360"),
+            ),
+            PostRef::new(
+                String::from("raoulmarks"),
+                String::from("1508829648121380870"),
+            ),
+        ];
+
+        assert_eq!(is_and_is, should_be);
+
+        let csv = "url,note\n\"/raoulmarks/status/1508829648121380870/photo/2\",x\n";
+        let json = r#"[
+            "/motionpunk1/status/1508819500044627978",
+            {"url": "/ehsan_parizi/status/1505375331871600641"}
+        ]"#;
+
+        assert_eq!(LinkImport::parse_links(csv, &LinkFormat::Csv).len(), 1);
+        assert_eq!(LinkImport::parse_links(json, &LinkFormat::Json).len(), 2);
+
+        // Failed links are kept as urls and read back when the import resumes.
+        for post in should_be.iter() {
+            assert_eq!(PostRef::from_url(post.url().as_str()).as_ref(), Some(post));
+        }
+
+        assert!(LinkImport::from_json_str(String::from("{\"path\": 1}")).is_err());
+    }
+
+    #[test]
+    fn test_timeline_page_parsing() {
+        let page: serde_json::Value = serde_json::from_str(
+            r#"{"data": {"instructions": [{"entries": [
+                {"content": {"itemContent": {"tweet_results": {"result": {
+                    "__typename": "Tweet",
+                    "rest_id": "200",
+                    "core": {"user_results": {"result": {"legacy": {"screen_name": "alice"}}}},
+                    "legacy": {
+                        "created_at": "Wed Oct 10 20:19:24 +0000 2018",
+                        "quoted_status_result": {"result": {"__typename": "Tweet", "rest_id": "1"}}
+                    }
+                }}}}},
+                {"content": {"cursorType": "Top", "value": "top-cursor"}},
+                {"content": {"cursorType": "Bottom", "value": "bottom-cursor"}}
+            ]}]}}"#,
+        )
+        .unwrap();
+
+        let posts = timeline_posts(&page);
+
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].post_id, "200");
+        assert_eq!(posts[0].username, "alice");
+        assert_eq!(posts[0].created_at.unwrap().timestamp(), 1539202764);
+        assert_eq!(bottom_cursor(&page), Some(String::from("bottom-cursor")));
+    }
+
+    #[test]
+    fn test_record_selector() {
+        let post = |id: &str, is_reply: bool| TimelinePost {
+            post_id: String::from(id),
+            username: String::from("alice"),
+            created_at: None,
+            is_reply,
+            is_retweet: false,
+            is_quote: false,
+            has_media: false,
+            is_pinned: false,
+            media: vec![],
+        };
+        let page = || vec![post("30", false), post("20", true), post("10", false)];
+
+        let mut selector = RecordSelector {
+            limit: Some(5),
+            ..Default::default()
+        };
+        selector.filters.replies = Inclusion::Exclude;
+
+        let (selected, done) = selector.select(page(), None, 0);
+        assert_eq!(selected.len(), 2);
+        assert!(!done);
+
+        let (selected, done) = selector.select(page(), None, 4);
+        assert_eq!(selected.len(), 1);
+        assert!(done);
+
+        selector.newer_than_last = true;
+
+        let (selected, done) = selector.select(page(), Some(20), 0);
+        assert_eq!(selected[0].post_id, "30");
+        assert_eq!(selected.len(), 1);
+        assert!(done);
+    }
+
+    #[test]
+    fn test_scrape_tab_urls() {
+        let profile = "https://twitter.com/alice/";
+
+        assert_eq!(tab_url(profile, &TweetType::Post), "https://twitter.com/alice");
+        assert_eq!(
+            tab_url(profile, &TweetType::Reply),
+            "https://twitter.com/alice/with_replies"
+        );
+        assert_eq!(tab_url(profile, &TweetType::Media), "https://twitter.com/alice/media");
+        assert_eq!(
+            tab_url("https://twitter.com/alice?s=20", &TweetType::Likes),
+            "https://twitter.com/alice/likes"
+        );
+    }
+
+    #[test]
+    fn test_scrape_article_fixtures() {
+        let pinned = parse_article(include_str!("../fixtures/timeline/pinned_media.html")).unwrap();
+
+        assert_eq!(pinned.post_id, "1508408582982799360");
+        assert_eq!(pinned.username, "alice");
+        assert!(pinned.is_pinned && pinned.has_media);
+        assert!(!pinned.is_retweet && !pinned.is_quote && !pinned.is_reply);
+        assert_eq!(pinned.created_at.unwrap().timestamp(), 1648468323);
+        assert_eq!(pinned.media.len(), 1);
+        assert_eq!(pinned.media[0].kind, MediaKind::Photo);
+
+        let retweet =
+            parse_article(include_str!("../fixtures/timeline/retweet_quote.html")).unwrap();
+
+        assert_eq!(retweet.username, "bob");
+        assert!(retweet.is_retweet && retweet.is_quote && !retweet.is_pinned);
+
+        let reply = parse_article(include_str!("../fixtures/timeline/reply.html")).unwrap();
+
+        assert!(reply.is_reply && !reply.has_media);
+        assert!(parse_article(include_str!("../fixtures/timeline/promoted.html")).is_none());
+    }
+
+    #[test]
+    fn test_job_next_fire() {
+        let action: crate::action::Action = serde_json::from_str(
+            r#"{"Capture": {"post": {"username": "a", "post_id": "1"}, "archive_dir": "x"}}"#,
+        )
+        .unwrap();
+        let start = chrono::DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let hours = chrono::Duration::hours;
+
+        let hourly = CronueueAction::new(start, action.clone(), ExecType::Forever).with_every(3600);
+
+        assert_eq!(hourly.next_fire(0, None), Some(start));
+        assert_eq!(hourly.next_fire(1, Some(start)), Some(start + hours(1)));
+        // The catch-up run after downtime went at 10:30, the next one is back on the hour.
+        assert_eq!(
+            hourly.next_fire(3, Some(start + hours(10) + chrono::Duration::minutes(30))),
+            Some(start + hours(11))
+        );
+
+        let once = CronueueAction::new(start, action.clone(), ExecType::Once);
+
+        assert_eq!(once.next_fire(1, Some(start)), None);
+
+        // The quota dropped the only run; the job is finished, not retried every minute.
+        let dropped = StoredJob {
+            name: String::from("once"),
+            job: once,
+            status: JobStatus::Active,
+            times_ran: 0,
+            times_dropped: 1,
+            last_run_at: Some(start),
+            updated_at: start,
+            source: None,
+        };
+
+        assert_eq!(dropped.slots_used(), 1);
+        assert_eq!(dropped.next_fire(), None);
+
+        let unspaced = CronueueAction::new(start, action, ExecType::Multiple(2));
+
+        assert_eq!(unspaced.next_fire(1, Some(start)), Some(start));
+        assert_eq!(unspaced.next_fire(2, Some(start)), None);
+    }
+
+    #[test]
+    fn test_job_file() {
+        let toml = r#"
+[[jobs]]
+name = "hourly-capture"
+start = "2026-01-01T00:00:00Z"
+every = "1h"
+limits = { max_runs = 3 }
+
+[jobs.action.Capture]
+post = { username = "a", post_id = "1" }
+archive_dir = "x"
+"#;
+
+        let defs = JobFile::new("jobs.toml").parse(toml).unwrap();
+
+        assert_eq!(defs.len(), 1);
+        assert_eq!(defs[0].job.exec_type(), &ExecType::Multiple(3));
+
+        let yaml = r#"
+jobs:
+  - name: once
+    start: "2026-01-01T00:00:00Z"
+    action: !Capture {post: {username: a, post_id: "1"}, archive_dir: x}
+  - name: broken
+    start: "2026-01-01T00:00:00Z"
+    every: 5 minutes
+    action: !Capture {post: {username: a, post_id: "1"}, archive_dir: x}
+"#;
+
+        let err = JobFile::new("jobs.yaml").parse(yaml).unwrap_err();
+
+        assert_eq!(err.problems().len(), 1);
+        assert!(err.problems()[0].starts_with("jobs.yaml:8: "));
+
+        let second = toml.replace("hourly-capture", "second").replace("= 3", "= 0");
+        let err = JobFile::new("jobs.toml").parse(&format!("{}{}", toml, toml)).unwrap_err();
+
+        assert!(err.problems()[0].starts_with("jobs.toml:13: "));
+
+        let err = JobFile::new("jobs.toml").parse(&format!("{}{}", toml, second)).unwrap_err();
+
+        assert!(err.problems()[0].starts_with("jobs.toml:16: "));
+
+        let overflow = toml.replace("\"1h\"", "\"99999999999999999d\"");
+        let err = JobFile::new("jobs.toml").parse(overflow.as_str()).unwrap_err();
+
+        assert!(err.problems()[0].starts_with("jobs.toml:5: "));
+        assert!(err.problems()[0].ends_with("is too long"));
+
+        let err = JobFile::new("jobs.toml").parse("[[jobs]]\nname = 1\n").unwrap_err();
+
+        assert!(err.problems()[0].starts_with("jobs.toml:2: "));
+    }
+
+    #[test]
+    fn test_thread_tree() {
+        let post = |id: &str, parent: Option<&str>| ThreadPost {
+            post_id: String::from(id),
+            username: String::from("alice"),
+            conversation_id: String::from("1"),
+            parent_id: parent.map(String::from),
+            text: String::new(),
+            created_at: None,
+        };
+
+        let posts = vec![
+            post("4", Some("2")),
+            post("3", Some("1")),
+            post("2", Some("1")),
+            post("1", None),
+        ];
+
+        let tree = build_tree(posts.clone(), "1").unwrap();
+
+        assert_eq!(tree.size(), 4);
+        assert_eq!(tree.replies[0].post.post_id, "2");
+        assert_eq!(tree.replies[0].replies[0].post.post_id, "4");
+
+        let without_root = build_tree(posts[..3].to_vec(), "1").unwrap();
+
+        assert_eq!(without_root.post.post_id, "2");
+        assert_eq!(without_root.size(), 2);
+    }
+
+    #[test]
+    fn test_throttle_wait_headers() {
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(throttle_wait(&headers), None);
+
+        headers.insert("x-rate-limit-remaining", HeaderValue::from_static("12"));
+        headers.insert("x-rate-limit-reset", HeaderValue::from_static("0"));
+
+        assert_eq!(throttle_wait(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+
+        assert_eq!(throttle_wait(&headers), Some(Duration::from_secs(120)));
+
+        let limit: BucketLimit = serde_json::from_str(r#"{"daily_cap": 100}"#).unwrap();
+
+        assert_eq!(limit.capacity, BucketLimit::default().capacity);
+        assert_eq!(limit.daily_cap, Some(100));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay_ms: 1000,
+            max_delay_ms: 5000,
+            multiplier: 2,
+        };
+
+        assert_eq!(policy.delay_for(1), Duration::from_millis(1000));
+        assert_eq!(policy.delay_for(3), Duration::from_millis(4000));
+        assert_eq!(policy.delay_for(4), Duration::from_millis(5000));
+        assert_eq!(policy.delay_for(u32::MAX), Duration::from_millis(5000));
+
+        let partial: RetryPolicy = serde_json::from_str(r#"{"max_attempts": 1}"#).unwrap();
+
+        assert_eq!(partial.max_attempts, 1);
+        assert_eq!(partial.base_delay_ms, RetryPolicy::default().base_delay_ms);
+    }
+
+    #[tokio::test]
+    async fn test_with_retry() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 0,
+            max_delay_ms: 0,
+            multiplier: 2,
+        };
+        let attempts = std::cell::Cell::new(0);
+
+        let ret: Result<u32, WebDriverError> = with_retry(&policy, "find", || {
+            attempts.set(attempts.get() + 1);
+
+            let attempt = attempts.get();
+
+            async move {
+                match attempt {
+                    1 => Err(no_such_element("not yet")),
+                    _ => Ok(attempt),
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(ret.unwrap(), 2);
+
+        attempts.set(0);
+
+        let ret: Result<(), WebDriverError> = with_retry(&policy, "find", || {
+            attempts.set(attempts.get() + 1);
+
+            async { Err(no_such_element("never")) }
+        })
+        .await;
+
+        assert!(ret.is_err());
+        assert_eq!(attempts.get(), 3);
+
+        // A click that timed out may have gone through, so it is not clicked again.
+        attempts.set(0);
+
+        let ret: Result<(), WebDriverError> =
+            with_retry_if(&policy, "like", click_not_delivered, || {
+                attempts.set(attempts.get() + 1);
+
+                async { Err(WebDriverError::Timeout(String::from("click"))) }
+            })
+            .await;
+
+        assert!(ret.is_err());
+        assert_eq!(attempts.get(), 1);
+        assert!(!bot_error(String::from("action cancelled")).is_retryable());
+    }
+
+    #[test]
+    fn test_step_log_and_report() {
+        let mut live = StepLog::new(ExecMode::Live);
+
+        assert!(live.record(StepKind::Navigate, "https://x.com/a/status/1", None));
+        assert!(live.record(StepKind::Click, "like", None));
+        assert!(!live.report("LikePost", None).dry_run);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut dry = StepLog::new(ExecMode::DryRun {
+            report_dir: Some(dir.path().to_str().unwrap().to_string()),
+        });
+
+        assert!(dry.record(StepKind::Resolve, "//button", None));
+        assert!(!dry.record(StepKind::Click, "retweet", None));
+        assert!(!dry.record(StepKind::Type, "tweetTextarea", Some("hi")));
+        dry.skip(StepKind::Resolve, "//confirm", None);
+        assert!(!dry.record(StepKind::Search, "https://x.com/search?q=a", None));
+
+        let skipped: Vec<bool> = dry.steps().iter().map(|x| x.skipped).collect();
+        assert_eq!(skipped, vec![false, true, true, true, true]);
+        assert_eq!(dry.steps()[2].text.as_deref(), Some("hi"));
+
+        let report = dry.report("Retweet", Some(String::from("boom")));
+        assert!(report.dry_run);
+        assert_eq!(report.steps.len(), 5);
+        assert_eq!(report.error.as_deref(), Some("boom"));
+
+        dry.emit(&report).unwrap();
+        let written: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(written.len(), 1);
+    }
+
+    #[test]
+    fn test_approval_decisions() {
+        assert_eq!(Decision::Approve.status(), ApprovalStatus::Approved);
+        assert_eq!(Decision::Reject.status(), ApprovalStatus::Rejected);
+        assert_eq!(Decision::Edit.status(), ApprovalStatus::Pending);
+        assert_eq!(Decision::Expire.status(), ApprovalStatus::Expired);
+
+        let created_at = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let pending: PendingAction = serde_json::from_value(serde_json::json!({
+            "id": "p1",
+            "job": "morning-post",
+            "action": {"LikePost": {"url": "https://x.com/a/status/1", "number": "First"}},
+            "rendered": "like",
+            "status": "Pending",
+            "created_at": created_at,
+            "decided_at": null,
+            "decided_by": null,
+            "note": null
+        }))
+        .unwrap();
+
+        assert_eq!(pending.account, "");
+        assert_eq!(
+            pending.expires_at(chrono::Duration::hours(24)),
+            created_at + chrono::Duration::days(1)
+        );
+    }
+
+    #[test]
+    fn test_audit_chain() {
+        let entry = |seq: i64, prev_hash: &str| {
+            let mut entry = AuditEntry {
+                seq,
+                job: String::from("morning-post"),
+                account: String::from("alice"),
+                action: String::from("PostText"),
+                target: None,
+                content_hash: None,
+                disclosure_label: None,
+                started_at: chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                finished_at: chrono::DateTime::from_timestamp(1_700_000_060, 0).unwrap(),
+                started_ms: 1_700_000_000_000,
+                outcome: AuditOutcome::Done,
+                artifacts: None,
+                decision: None,
+                prev_hash: prev_hash.to_string(),
+                hash: String::new(),
+            };
+            entry.hash = entry.compute_hash();
+            entry
+        };
+
+        let first = entry(0, &"0".repeat(64));
+        let second = entry(1, first.hash.as_str());
+
+        assert_eq!(first.compute_hash(), first.hash);
+        assert_ne!(first.hash, entry(0, &"1".repeat(64)).hash);
+
+        let mut chain = ChainCheck::new();
+        assert!(chain.push(&first));
+        assert!(chain.push(&second));
+        assert_eq!(chain.next_seq(), 2);
+
+        let mut altered = second.clone();
+        altered.outcome = AuditOutcome::Failed(String::from("hidden"));
+
+        let mut chain = ChainCheck::new();
+        assert!(chain.push(&first));
+        assert!(!chain.push(&altered));
+        assert_eq!(chain.next_seq(), 1);
+
+        let mut chain = ChainCheck::new();
+        assert!(!chain.push(&second));
+        assert_eq!(chain.next_seq(), 0);
+
+        // Entries without a decision hash as they did before the field existed.
+        assert!(!serde_json::to_string(&first).unwrap().contains("decision"));
+
+        let mut decided = entry(2, second.hash.as_str());
+        decided.outcome = AuditOutcome::Decided;
+        decided.decision = Some(ApprovalDecision {
+            pending_id: String::from("p1"),
+            decision: Decision::Edit,
+            by: String::from("bob"),
+            note: None,
+            before: String::from("helo"),
+            after: String::from("hello"),
+        });
+        decided.hash = decided.compute_hash();
+
+        let mut chain = ChainCheck::new();
+        assert!(chain.push(&first) && chain.push(&second) && chain.push(&decided));
+
+        decided.decision.as_mut().unwrap().by = String::from("mallory");
+
+        let mut chain = ChainCheck::new();
+        assert!(chain.push(&first) && chain.push(&second));
+        assert!(!chain.push(&decided));
+    }
+
+    #[test]
+    fn test_bot_stop_token() {
+        let first = CancellationToken::new();
+        let second = CancellationToken::new();
+        let job = JobControl::child_of(&first);
+        let other = JobControl::child_of(&second);
+
+        first.cancel();
+
+        assert!(job.is_cancelled());
+        assert!(!other.is_cancelled());
+        assert!(!JobControl::new().is_cancelled());
+    }
+
+    #[test]
+    fn test_disclosure_label() {
+        let policy = DisclosurePolicy {
+            max_length: 40,
+            ..Default::default()
+        };
+
+        let labeled = policy.apply("hello").unwrap();
+
+        assert_eq!(labeled, "hello\n\n[automated account]");
+        assert_eq!(policy.apply(labeled.as_str()).unwrap(), labeled);
+        assert!(policy.apply("this text fits on its own").is_err());
+
+        let partial: DisclosurePolicy = serde_json::from_str(r#"{"label": "[bot]"}"#).unwrap();
+
+        assert_eq!(partial.label, "[bot]");
+        assert_eq!(partial.max_length, DisclosurePolicy::default().max_length);
+    }
+
+    #[test]
+    fn test_content_policy_check() {
+        let policy = ContentPolicy {
+            banned_terms: vec![String::from("giveaway")],
+            flag_only: vec![CheckKind::TooManyMentions],
+            max_mentions: 1,
+            ..Default::default()
+        };
+        let label = "[automated account]";
+        let recent = vec![String::from("Morning all\n\n[automated account]")];
+
+        let clean = policy.check("Shipping the new release today", &recent, label);
+        assert!(clean.findings.is_empty());
+
+        let flagged = policy.check("thanks @alice and @bob", &recent, label);
+        assert_eq!(flagged.findings[0].severity, Severity::Flag);
+        assert!(!flagged.is_rejected());
+
+        assert!(policy.check("Huge GIVEAWAY now", &recent, label).is_rejected());
+        assert!(policy.check("morning   all!", &recent, label).is_rejected());
+
+        let partial: ContentPolicy = serde_json::from_str(r#"{"max_links": 0}"#).unwrap();
+
+        assert_eq!(partial.max_links, 0);
+        assert_eq!(partial.max_length, ContentPolicy::default().max_length);
+    }
+
+    #[test]
+    fn test_quota_windows_and_limits() {
+        let at = chrono::DateTime::parse_from_rfc3339("2026-10-19T13:45:10Z").unwrap().to_utc();
+        let day = chrono::DateTime::parse_from_rfc3339("2026-10-19T00:00:00Z").unwrap().to_utc();
+
+        assert_eq!(QuotaWindow::Hour.start(at), day + chrono::Duration::hours(13));
+        assert_eq!(QuotaWindow::Day.start(at), day);
+
+        let like: crate::action::Action = serde_json::from_str(
+            r#"{"LikePost": {"url": "https://x.com/a/status/1", "number": "First"}}"#,
+        )
+        .unwrap();
+        let capture: crate::action::Action = serde_json::from_str(
+            r#"{"Capture": {"post": {"username": "a", "post_id": "1"}, "archive_dir": "x"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(QuotaKind::of(&like), QuotaKind::Likes);
+        assert_eq!(QuotaKind::of(&capture), QuotaKind::RecordFetches);
+
+        let config = QuotaConfig {
+            limits: vec![
+                QuotaLimit {
+                    kind: QuotaKind::Likes,
+                    per_hour: Some(10),
+                    per_day: Some(50),
+                },
+                QuotaLimit {
+                    kind: QuotaKind::Likes,
+                    per_hour: Some(5),
+                    per_day: None,
+                },
+            ],
+            over_quota: OverQuota::Drop,
+        };
+
+        assert_eq!(config.limit_for(QuotaKind::Likes, QuotaWindow::Hour), Some(5));
+        assert_eq!(config.limit_for(QuotaKind::Posts, QuotaWindow::Day), None);
+
+        let partial: QuotaConfig = serde_json::from_str(r#"{"over_quota": "Drop"}"#).unwrap();
+
+        assert_eq!(partial.over_quota, OverQuota::Drop);
+        assert!(partial.limits.is_empty());
+
+        let used = [(QuotaWindow::Hour, 4), (QuotaWindow::Day, 4)];
+        assert_eq!(config.over_limit(QuotaKind::Likes, &used, at), None);
+
+        let used = [(QuotaWindow::Hour, 4), (QuotaWindow::Day, 50)];
+        let exceeded = config.over_limit(QuotaKind::Likes, &used, at).unwrap();
+
+        assert_eq!(exceeded.window, QuotaWindow::Day);
+        assert_eq!(exceeded.limit, 50);
+        assert_eq!(exceeded.resets_at, day + chrono::Duration::days(1));
+    }
+
+    #[test]
+    fn test_rate_limiter_reconfigure_keeps_buckets() {
+        let capped = RateLimitConfig {
+            default: BucketLimit {
+                capacity: 3,
+                refill_per_minute: 1,
+                daily_cap: Some(1),
+            },
+            rules: vec![],
+        };
+        let mut limiter = RateLimiter::new(capped.clone());
+
+        assert_eq!(limiter.try_take("x.com", &OpKind::Post).unwrap(), None);
+        assert!(limiter.try_take("x.com", &OpKind::Post).is_err());
+
+        limiter.reconfigure(capped.clone());
+        assert!(limiter.try_take("x.com", &OpKind::Post).is_err());
+
+        let mut changed = capped;
+        changed.rules.push(RateRule {
+            host: None,
+            op: Some(OpKind::Search),
+            limit: BucketLimit::default(),
+        });
+
+        limiter.reconfigure(changed);
+        assert!(limiter.try_take("x.com", &OpKind::Post).is_err());
+        assert_eq!(limiter.try_take("x.com", &OpKind::Search).unwrap(), None);
+    }
+
+    #[test]
+    fn test_session_health_and_fired() {
+        let unreachable = SessionHealth::Unreachable(String::from("timeout"));
+
+        let wrong = SessionHealth::WrongAccount {
+            expected: String::from("a"),
+            found: String::from("b"),
+        };
+
+        assert!(SessionHealth::LoginWall.needs_relogin());
+        assert!(wrong.needs_relogin());
+        assert!(wrong.to_error().to_string().contains("re-import cookies"));
+        assert!(!unreachable.needs_relogin());
+        assert!(!unreachable.to_error().to_string().contains("re-import cookies"));
+
+        assert_eq!(Fired::Ran(AuditOutcome::Done).outcome(), Some(AuditOutcome::Done));
+        assert_eq!(Fired::Dropped.outcome(), None);
+        assert_eq!(Fired::Skipped.outcome(), None);
+
+        let check: HealthCheck = serde_json::from_str(r#"{"enabled": false}"#).unwrap();
+
+        assert_eq!(check.check_url, HealthCheck::default().check_url);
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.json");
+        let path = path.to_str().unwrap();
+
+        write_to_file_atomic(path, String::from("[]")).unwrap();
+        write_to_file_atomic(path, String::from("[{}]")).unwrap();
+
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "[{}]");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_timeline_pinned_entry() {
+        let tweet = |id: &str| {
+            format!(
+                r#"{{"tweet_results": {{"result": {{"__typename": "Tweet", "rest_id": "{}",
+                    "legacy": {{}}}}}}}}"#,
+                id
+            )
+        };
+        let page: serde_json::Value = serde_json::from_str(
+            format!(
+                r#"{{"data": {{"instructions": [
+                    {{"type": "TimelinePinEntry", "entry": {{"content": {{"itemContent": {}}}}}}},
+                    {{"type": "TimelineAddEntries", "entries": [
+                        {{"content": {{"itemContent": {}}}}},
+                        {{"content": {{"itemContent": {}}}}}
+                    ]}}
+                ]}}}}"#,
+                tweet("5"),
+                tweet("300"),
+                tweet("200")
+            )
+            .as_str(),
+        )
+        .unwrap();
+
+        let posts = timeline_posts(&page);
+        let pinned: Vec<bool> = posts.iter().map(|x| x.is_pinned).collect();
+
+        assert_eq!(pinned, vec![true, false, false]);
+
+        let selector = RecordSelector {
+            newer_than_last: true,
+            ..Default::default()
+        };
+
+        let (selected, done) = selector.select(posts, Some(250), 0);
+
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].post_id, "300");
+        assert!(done);
+    }
+
+    #[test]
+    fn test_thread_depths_and_cursors() {
+        let post = |id: &str, parent: Option<&str>| ThreadPost {
+            post_id: String::from(id),
+            username: String::from("alice"),
+            conversation_id: String::from("1"),
+            parent_id: parent.map(String::from),
+            text: String::new(),
+            created_at: None,
+        };
+        let posts = vec![
+            post("1", None),
+            post("2", Some("1")),
+            post("3", Some("1")),
+            post("6", Some("5")),
+            post("5", Some("4")),
+            post("4", Some("2")),
+            post("7", Some("3")),
+        ];
+        let mut depths = std::collections::HashMap::from([(String::from("2"), 0i64)]);
+
+        place_posts(&posts, &mut depths);
+
+        let depth = |id: &str| depths.get(id).copied();
+        assert_eq!(depth("1"), Some(-1));
+        assert_eq!(depth("4"), Some(1));
+        assert_eq!(depth("6"), Some(3));
+        assert_eq!(depth("3"), None);
+        assert_eq!(depth("7"), None);
+
+        let page: serde_json::Value = serde_json::from_str(
+            r#"{"entries": [
+                {"content": {"cursorType": "Top", "value": "top"}},
+                {"content": {"items": [{"item": {"itemContent": {
+                    "cursorType": "ShowMore", "value": "more"
+                }}}]}},
+                {"content": {"itemContent": {"cursorType": "Bottom", "value": "bottom"}}}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(thread_cursors(&page), vec!["more", "bottom"]);
+    }
+
+    #[test]
+    fn test_media_refs_and_paths() {
+        let legacy = serde_json::json!({
+            "entities": {"media": [
+                {"media_url_https": "https://pbs.twimg.com/media/a.jpg", "type": "photo"}
+            ]},
+            "extended_entities": {"media": [
+                {"media_url_https": "https://pbs.twimg.com/media/a.jpg", "type": "photo"},
+                {"media_url_https": "https://pbs.twimg.com/tweet_video_thumb/b", "type": "video"}
+            ]}
+        });
+
+        let refs = MediaRef::from_legacy(&legacy);
+
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[0].kind, MediaKind::Photo);
+        assert_eq!(refs[1].kind, MediaKind::VideoThumbnail);
+        assert!(MediaRef::from_legacy(&serde_json::json!({"entities": {}})).is_empty());
+
+        let html = concat!(
+            r#"<img src="https://pbs.twimg.com/media/A1?format=jpg&amp;name=small">"#,
+            r#"<img src="https://pbs.twimg.com/media/A1?format=jpg&amp;name=small">"#,
+            r#"<img src="https://pbs.twimg.com/profile_images/1/avatar.jpg">"#,
+            r#"<img src="https://pbs.twimg.com/tweet_video_thumb/B2.jpg">"#,
+        );
+
+        assert_eq!(
+            MediaRef::from_html(html),
+            vec![
+                MediaRef {
+                    url: String::from("https://pbs.twimg.com/media/A1?format=jpg&name=small"),
+                    kind: MediaKind::Photo,
+                },
+                MediaRef {
+                    url: String::from("https://pbs.twimg.com/tweet_video_thumb/B2.jpg"),
+                    kind: MediaKind::VideoThumbnail,
+                },
+            ]
+        );
+
+        assert_eq!(mime_of(&ImageType::Jpeg), Some("image/jpeg"));
+        assert_eq!(mime_of(&ImageType::Webp), Some("image/webp"));
+        assert_eq!(mime_of(&ImageType::Bmp), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive = MediaArchive {
+            dir: dir.path().to_str().unwrap().to_string(),
+            max_file_bytes: 1024,
+            max_total_bytes: 4096,
+            retry: RetryPolicy::default(),
+        };
+
+        let jpg = archive.path_for("abcdef", "image/jpeg");
+
+        assert_eq!(jpg, dir.path().join("ab").join("abcdef.jpg").to_str().unwrap());
+        assert!(archive.path_for("cdef01", "image/png").ends_with("cd/cdef01.png"));
+
+        assert_eq!(dir_size(dir.path()), 0);
+
+        std::fs::create_dir_all(dir.path().join("ab")).unwrap();
+        std::fs::write(jpg.as_str(), b"1234").unwrap();
+        std::fs::write(dir.path().join("other"), b"567").unwrap();
+
+        assert_eq!(dir_size(dir.path()), 7);
+        assert_eq!(dir_size(dir.path().join("missing").as_path()), 0);
+    }
+
+    #[test]
+    fn test_capture_warc_and_manifest() {
+        let at = chrono::DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let page_info = serde_json::json!({"userAgent": "test-agent"});
+        let fetched = serde_json::json!({
+            "status": 200,
+            "statusText": "OK",
+            "headers": [["content-type", "text/html"], ["x-frame-options", "DENY"]]
+        });
+
+        let refetch = refetch_fields(&fetched).unwrap();
+
+        assert!(refetch.starts_with("note: the page fetched its own url again"));
+        assert!(refetch.contains("\r\nstatus: 200 OK\r\nheader: content-type: text/html\r\n"));
+        assert!(refetch.ends_with("header: x-frame-options: DENY\r\n"));
+        assert_eq!(refetch_fields(&serde_json::json!({"error": "offline"})), None);
+
+        let url = "https://twitter.com/a/status/1";
+        let warc = warc_records(url, at, &page_info, Some(&refetch), b"<html></html>", b"png");
+        let warc = String::from_utf8(warc).unwrap();
+
+        let kinds: Vec<&str> = warc
+            .lines()
+            .filter_map(|x| x.strip_prefix("WARC-Type: "))
+            .collect();
+
+        assert_eq!(kinds, vec!["warcinfo", "resource", "resource", "metadata", "metadata"]);
+        assert!(!warc.contains("WARC-Type: response") && !warc.contains("WARC-Type: request"));
+        assert!(warc.contains("WARC-Date: 2026-01-01T00:00:00.000Z\r\n"));
+        assert!(warc.contains(format!("sha256:{}\r\n", sha256_hex(b"png")).as_str()));
+
+        let ids: Vec<&str> = warc
+            .lines()
+            .filter_map(|x| x.strip_prefix("WARC-Record-ID: "))
+            .collect();
+        let concurrent: Vec<&str> = warc
+            .lines()
+            .filter_map(|x| x.strip_prefix("WARC-Concurrent-To: "))
+            .collect();
+
+        assert_eq!(concurrent, vec![ids[1]; 3]);
+
+        let without = warc_records(url, at, &page_info, None, b"", b"");
+
+        assert_eq!(String::from_utf8(without).unwrap().matches("WARC-Type: metadata").count(), 1);
+
+        let id = record_id();
+        let groups: Vec<usize> = id.split('-').map(|x| x.len()).collect();
+
+        assert_eq!(groups, vec![8, 4, 4, 4, 12]);
+        assert_eq!(&id[14..15], "4");
+        assert!("89ab".contains(&id[19..20]));
+        assert_ne!(id, record_id());
+
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = CaptureManifest {
+            url: url.to_string(),
+            final_url: url.to_string(),
+            username: String::from("a"),
+            post_id: String::from("1"),
+            captured_at: at,
+            files: vec![CapturedFile {
+                name: String::from("page.html"),
+                sha256: sha256_hex(b"<html></html>"),
+                bytes: 13,
+            }],
+        };
+
+        write_manifest(dir.path().to_str().unwrap(), &manifest).unwrap();
+
+        let json = std::fs::read(dir.path().join("manifest.json")).unwrap();
+        let sums = std::fs::read_to_string(dir.path().join("manifest.sha256")).unwrap();
+
+        assert_eq!(serde_json::from_slice::<CaptureManifest>(&json).unwrap(), manifest);
+        assert_eq!(
+            sums,
+            format!(
+                "{}  page.html\n{}  manifest.json\n",
+                sha256_hex(b"<html></html>"),
+                sha256_hex(&json)
+            )
+        );
+
+        let capture = Capture::from_json_str(
+            serde_json::json!({
+                "post": {"username": "a", "post_id": "1"},
+                "archive_dir": dir.path().join("captures").to_str().unwrap()
+            })
+            .to_string(),
+        );
+
+        let first = capture.create_capture_dir(at).unwrap();
+        let second = capture.create_capture_dir(at).unwrap();
+
+        assert!(first.ends_with("a-1-20260101T000000.000Z"));
+        assert_eq!(second, format!("{}-1", first));
+    }
+
+    #[test]
+    fn test_failure_artifact_retention() {
+        let at = |x: &str| chrono::DateTime::parse_from_rfc3339(x).unwrap().to_utc();
+
+        assert_eq!(sanitize("likes/@alice-2"), "likes__alice_2");
+
+        let dir = tempfile::tempdir().unwrap();
+        let names = [
+            "20260901T000000.000Z-old",
+            "20261017T080000.000Z-a",
+            "20261018T080000.000Z-b",
+            "20261019T110000.500Z-c",
+        ];
+
+        for name in names {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+        }
+
+        std::fs::write(dir.path().join("notes.txt"), b"kept").unwrap();
+
+        assert_eq!(failed_at_of(Path::new(names[3])), Some(at("2026-10-19T11:00:00.500Z")));
+        assert_eq!(failed_at_of(Path::new("notes.txt")), None);
+
+        let mut failures = FailureArtifacts {
+            enabled: true,
+            dir: dir.path().to_str().unwrap().to_string(),
+            max_kept: 10,
+            max_age_days: Some(14),
+        };
+
+        let left = || {
+            let mut left: Vec<String> = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|x| x.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            left.sort();
+
+            left
+        };
+
+        failures.prune(at("2026-10-19T12:00:00Z"));
+
+        assert_eq!(left(), vec![names[1], names[2], names[3], "notes.txt"]);
+
+        failures.max_kept = 2;
+        failures.prune(at("2026-10-19T12:00:00Z"));
+
+        assert_eq!(left(), vec![names[2], names[3], "notes.txt"]);
+
+        let linked = link_artifacts(bot_error(String::from("boom")), Some("failures/x"));
+
+        assert!(linked.to_string().contains("boom (failure artifacts in failures/x)"));
+        assert_eq!(
+            link_artifacts(bot_error(String::from("boom")), None).to_string(),
+            bot_error(String::from("boom")).to_string()
+        );
+
+        let partial: FailureArtifacts = serde_json::from_str(r#"{"max_kept": 3}"#).unwrap();
+
+        assert_eq!(partial.max_kept, 3);
+        assert_eq!(partial.dir, FailureArtifacts::default().dir);
+    }
+
+    #[cfg(feature = "http-api")]
+    #[tokio::test]
+    async fn test_api_token_and_openapi() {
+        let routes = [
+            ("get", "/jobs"),
+            ("post", "/jobs"),
+            ("delete", "/jobs/{name}"),
+            ("post", "/jobs/{name}/pause"),
+            ("post", "/jobs/{name}/resume"),
+            ("get", "/jobs/{name}/runs"),
+            ("get", "/approvals"),
+            ("post", "/approvals/{id}/approve"),
+            ("post", "/approvals/{id}/reject"),
+            ("post", "/approvals/{id}/edit"),
+        ];
+
+        let doc: serde_json::Value = serde_json::from_str(openapi_json().as_str()).unwrap();
+        let documented: usize = doc["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|x| x.as_object().unwrap().len())
+            .sum();
+
+        assert_eq!(documented, routes.len());
+
+        // Nothing is served on this port; the token check answers before any handler.
+        let client = mongodb::Client::with_uri_str("mongodb://127.0.0.1:9").await.unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let app = router(client.database("test"), "secret");
+
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let http = reqwest::Client::new();
+
+        for (method, path) in routes {
+            assert!(doc["paths"][path][method].is_object(), "{} {} undocumented", method, path);
+
+            let url = format!("http://{}{}", addr, path.replace(['{', '}'], ""));
+            let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+
+            let res = http.request(method.clone(), url.as_str()).send().await.unwrap();
+            assert_eq!(res.status(), 401, "{} {}", method, path);
+
+            let res = http.request(method, url.as_str()).bearer_auth("wrong").send().await.unwrap();
+            assert_eq!(res.status(), 401);
+        }
+
+        let res = http.get(format!("http://{}/openapi.json", addr)).send().await.unwrap();
+        assert_eq!(res.status(), 200);
+    }
 }
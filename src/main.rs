@@ -0,0 +1,496 @@
+use chrono::{NaiveDate, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use rusty_bot_swarm::{
+    recorded_posts, Action, ApprovalQueue, AuditOutcome, Bot, Config, CronueueAction, JobStatus,
+    JobStore,
+};
+use serde::Serialize;
+use std::error::Error;
+use std::fs::read_to_string;
+use std::io::Read;
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(name = "rusty-bot-swarm", version, about = "Manage bot jobs and post recorders")]
+struct Cli {
+    /// Path to the bot config, a JSON file.
+    #[arg(long, short, global = true, default_value = "config.json")]
+    config: String,
+
+    /// Path to the proxy settings, a JSON file. Needed by commands that open a browser.
+    #[arg(long, global = true)]
+    proxy: Option<String>,
+
+    #[arg(long, global = true, value_enum, default_value_t = Format::Human)]
+    format: Format,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Human,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check the config without starting a browser.
+    Validate,
+
+    /// Manage the jobs the daemon runs.
+    #[command(subcommand)]
+    Jobs(JobsCommand),
+
+    /// Decide on actions waiting for approval.
+    #[command(subcommand)]
+    Approvals(ApprovalsCommand),
+
+    /// Run one action right away. Pass `-` to read it from stdin.
+    Run {
+        action: String,
+
+        /// Walk through the steps without clicking, typing or recording anything.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Where to write the dry-run report.
+        #[arg(long, requires = "dry_run")]
+        report_dir: Option<String>,
+    },
+
+    /// Run the jobs in the job store until interrupted.
+    Daemon {
+        /// Seconds between checks for changed jobs.
+        #[arg(long, default_value_t = 30)]
+        poll_secs: u64,
+    },
+
+    /// Export recorded posts for a range of days.
+    Export {
+        #[arg(long)]
+        from: NaiveDate,
+
+        /// Last day to export. Defaults to today.
+        #[arg(long)]
+        to: Option<NaiveDate>,
+
+        /// File to write to instead of stdout.
+        #[arg(long)]
+        out: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum JobsCommand {
+    /// List jobs with their status and next run.
+    List,
+
+    /// Add a job from a `CronueueAction` JSON file, or `-` for stdin.
+    Add { name: String, job: String },
+
+    Pause { name: String },
+
+    Resume { name: String },
+
+    Remove { name: String },
+}
+
+#[derive(Subcommand)]
+enum ApprovalsCommand {
+    /// List actions waiting for a decision.
+    List,
+
+    Approve {
+        id: String,
+
+        /// Who signed the action off, for the decision log.
+        #[arg(long)]
+        by: String,
+    },
+
+    Reject {
+        id: String,
+
+        #[arg(long)]
+        by: String,
+
+        #[arg(long)]
+        reason: Option<String>,
+    },
+
+    /// Replace the action with one from an `Action` JSON file, or `-` for stdin.
+    /// It still needs approving afterwards.
+    Edit {
+        id: String,
+        action: String,
+
+        #[arg(long)]
+        by: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Done<'a> {
+    ok: bool,
+    message: &'a str,
+}
+
+type CliResult = Result<(), Box<dyn Error>>;
+
+fn read_input(path: &str) -> Result<String, Box<dyn Error>> {
+    if path == "-" {
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+
+        return Ok(input);
+    }
+
+    Ok(read_to_string(path)?)
+}
+
+fn emit<T: Serialize>(format: Format, value: &T, human: String) -> CliResult {
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        Format::Human => println!("{}", human),
+    }
+
+    Ok(())
+}
+
+fn done(format: Format, message: &str) -> CliResult {
+    emit(format, &Done { ok: true, message }, message.to_string())
+}
+
+fn load_config(path: &str) -> Result<(String, Config), Box<dyn Error>> {
+    let raw = read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let config = Config::parse(raw.as_str()).map_err(|e| format!("{}: {}", path, e))?;
+
+    Ok((raw, config))
+}
+
+async fn start_bot(cli: &Cli, config_raw: String) -> Result<Bot, Box<dyn Error>> {
+    let proxy_path = cli.proxy.as_deref().ok_or("this command needs --proxy")?;
+    let proxy_raw = read_to_string(proxy_path)?;
+
+    Ok(Bot::start(String::from("cli"), proxy_raw, config_raw).await?)
+}
+
+async fn validate(cli: &Cli) -> CliResult {
+    let (_, config) = load_config(cli.config.as_str())?;
+    let problems = config.problems();
+
+    let human = if problems.is_empty() {
+        format!("{} is valid", cli.config)
+    } else {
+        problems.iter().map(|x| format!("- {}", x)).collect::<Vec<_>>().join("\n")
+    };
+
+    emit(cli.format, &problems, human)?;
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} problem(s) in {}", problems.len(), cli.config).into())
+    }
+}
+
+async fn jobs(cli: &Cli, command: &JobsCommand) -> CliResult {
+    let (_, config) = load_config(cli.config.as_str())?;
+    let db = config.create_db().await;
+    let store = JobStore::new(&db);
+
+    match command {
+        JobsCommand::List => {
+            let jobs = store.list().await?;
+
+            let human = jobs
+                .iter()
+                .map(|x| {
+                    let status = format!("{:?}", x.status);
+                    let next = match x.next_fire() {
+                        Some(next) => next.to_rfc3339(),
+                        None => String::from("finished"),
+                    };
+
+                    format!(
+                        "{:<24} {:<8} {:<14} runs: {:<5} next: {}",
+                        x.name,
+                        status,
+                        x.job.action().kind(),
+                        x.times_ran,
+                        next
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            emit(cli.format, &jobs, human)
+        }
+        JobsCommand::Add { name, job } => {
+            let job: CronueueAction = serde_json::from_str(read_input(job)?.as_str())?;
+
+            store.add(name, job).await?;
+
+            done(cli.format, format!("added job {}", name).as_str())
+        }
+        JobsCommand::Pause { name } => {
+            store.set_status(name, JobStatus::Paused).await?;
+
+            done(cli.format, format!("paused job {}", name).as_str())
+        }
+        JobsCommand::Resume { name } => {
+            store.set_status(name, JobStatus::Active).await?;
+
+            done(cli.format, format!("resumed job {}", name).as_str())
+        }
+        JobsCommand::Remove { name } => {
+            store.remove(name).await?;
+
+            done(cli.format, format!("removed job {}", name).as_str())
+        }
+    }
+}
+
+async fn approvals(cli: &Cli, command: &ApprovalsCommand) -> CliResult {
+    let (_, config) = load_config(cli.config.as_str())?;
+    let db = config.create_db().await;
+    let queue = ApprovalQueue::new(&db);
+
+    match command {
+        ApprovalsCommand::List => {
+            let pending = queue.list_pending().await?;
+
+            let human = pending
+                .iter()
+                .map(|x| {
+                    format!(
+                        "{:<26} {:<24} {:<14} {}\n    {}",
+                        x.id,
+                        x.job,
+                        x.action.kind(),
+                        x.created_at.to_rfc3339(),
+                        x.rendered
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            emit(cli.format, &pending, human)
+        }
+        ApprovalsCommand::Approve { id, by } => {
+            queue.approve(id, by).await?;
+
+            done(cli.format, format!("approved {}", id).as_str())
+        }
+        ApprovalsCommand::Reject { id, by, reason } => {
+            queue.reject(id, by, reason.clone()).await?;
+
+            done(cli.format, format!("rejected {}", id).as_str())
+        }
+        ApprovalsCommand::Edit { id, action, by } => {
+            let action: Action = serde_json::from_str(read_input(action)?.as_str())?;
+
+            queue.edit(id, by, action).await?;
+
+            done(cli.format, format!("edited {}, it still needs approving", id).as_str())
+        }
+    }
+}
+
+async fn run(cli: &Cli, action: &str, dry_run: bool, report_dir: Option<String>) -> CliResult {
+    let action: Action = serde_json::from_str(read_input(action)?.as_str())?;
+    let (raw, _) = load_config(cli.config.as_str())?;
+    let bot = start_bot(cli, raw).await?;
+
+    let result = if dry_run {
+        let report = bot.dry_run(action, report_dir).await;
+
+        let human = report
+            .steps
+            .iter()
+            .map(|x| {
+                let skipped = if x.skipped { " (skipped)" } else { "" };
+
+                format!("{:?} {}{}", x.kind, x.target, skipped)
+            })
+            .chain(report.error.iter().map(|x| format!("error: {}", x)))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        emit(cli.format, &report, human)?;
+
+        match report.error {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    } else {
+        let outcome = bot.run_now(action).await;
+
+        let human = match &outcome {
+            Some(AuditOutcome::Failed(error)) => format!("failed: {}", error),
+            Some(outcome) => format!("{:?}", outcome),
+            None => String::from("skipped, see the log for why"),
+        };
+
+        emit(cli.format, &outcome, human)?;
+
+        match outcome {
+            Some(AuditOutcome::Failed(error)) => Err(error.into()),
+            None => Err("the action did not run".into()),
+            _ => Ok(()),
+        }
+    };
+
+    bot.shutdown().await?;
+
+    result
+}
+
+async fn daemon(cli: &Cli, poll_secs: u64) -> CliResult {
+    let (raw, _) = load_config(cli.config.as_str())?;
+    let bot = start_bot(cli, raw).await?;
+
+    bot.stop_on_signals().await;
+
+    #[cfg(feature = "http-api")]
+    bot.start_api().await;
+
+    bot.run_daemon(Duration::from_secs(poll_secs)).await;
+    bot.shutdown().await?;
+
+    Ok(())
+}
+
+async fn export(
+    cli: &Cli,
+    from: NaiveDate,
+    to: Option<NaiveDate>,
+    out: Option<&str>,
+) -> CliResult {
+    let (_, config) = load_config(cli.config.as_str())?;
+    let db = config.create_db().await;
+
+    let to = to.unwrap_or_else(|| Utc::now().naive_utc().date());
+    let posts = recorded_posts(&db, from, to).await?;
+
+    let text = match cli.format {
+        Format::Json => serde_json::to_string_pretty(&posts)?,
+        Format::Human => posts
+            .iter()
+            .map(|x| format!("https://twitter.com/{}/status/{}", x.username, x.post))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    match out {
+        Some(path) => {
+            std::fs::write(path, text)?;
+
+            eprintln!("exported {} posts to {}", posts.len(), path);
+        }
+        None => println!("{}", text),
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+
+    let cli = Cli::parse();
+
+    let result = match &cli.command {
+        Command::Validate => validate(&cli).await,
+        Command::Jobs(command) => jobs(&cli, command).await,
+        Command::Approvals(command) => approvals(&cli, command).await,
+        Command::Run {
+            action,
+            dry_run,
+            report_dir,
+        } => run(&cli, action, *dry_run, report_dir.clone()).await,
+        Command::Daemon { poll_secs } => daemon(&cli, *poll_secs).await,
+        Command::Export { from, to, out } => export(&cli, *from, *to, out.as_deref()).await,
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+
+    #[test]
+    fn test_cli_parse() {
+        let cli = Cli::try_parse_from(["bot", "validate"]).unwrap();
+        assert_eq!(cli.config, "config.json");
+        assert!(cli.proxy.is_none());
+        assert!(matches!(cli.command, Command::Validate));
+
+        let args = ["bot", "jobs", "add", "likes", "-", "-c", "c.json"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(cli.config, "c.json");
+        assert!(matches!(
+            cli.command,
+            Command::Jobs(JobsCommand::Add { ref name, ref job }) if name == "likes" && job == "-"
+        ));
+
+        let cli = Cli::try_parse_from(["bot", "--format", "json", "jobs", "list"]).unwrap();
+        assert!(cli.format == Format::Json);
+        assert!(matches!(cli.command, Command::Jobs(JobsCommand::List)));
+
+        let cli = Cli::try_parse_from(["bot", "approvals", "reject", "p1", "--by", "ana"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Approvals(ApprovalsCommand::Reject { ref id, ref by, reason: None })
+                if id == "p1" && by == "ana"
+        ));
+
+        let cli = Cli::try_parse_from(["bot", "approvals", "edit", "p1", "a.json", "--by", "ana"]);
+        assert!(matches!(
+            cli.unwrap().command,
+            Command::Approvals(ApprovalsCommand::Edit { ref action, .. }) if action == "a.json"
+        ));
+
+        let missing_by = Cli::try_parse_from(["bot", "approvals", "approve", "p1"]);
+        assert!(missing_by.is_err());
+
+        let cli = Cli::try_parse_from(["bot", "daemon"]).unwrap();
+        assert!(matches!(cli.command, Command::Daemon { poll_secs: 30 }));
+
+        let cli = Cli::try_parse_from(["bot", "export", "--from", "2026-10-01"]).unwrap();
+        match cli.command {
+            Command::Export { from, to, out } => {
+                assert_eq!(from, NaiveDate::from_ymd_opt(2026, 10, 1).unwrap());
+                assert!(to.is_none() && out.is_none());
+            }
+            _ => panic!("expected export"),
+        }
+
+        let bad_date = Cli::try_parse_from(["bot", "export", "--from", "yesterday"]);
+        assert!(bad_date.is_err());
+
+        let cli = Cli::try_parse_from(["bot", "run", "a.json", "--dry-run", "--report-dir", "r"]);
+        assert!(matches!(
+            cli.unwrap().command,
+            Command::Run { dry_run: true, report_dir: Some(ref dir), .. } if dir == "r"
+        ));
+
+        let no_dry_run = Cli::try_parse_from(["bot", "run", "a.json", "--report-dir", "r"]);
+        assert_eq!(no_dry_run.err().unwrap().kind(), ErrorKind::MissingRequiredArgument);
+    }
+}
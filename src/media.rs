@@ -0,0 +1,341 @@
+use crate::rate_limit::{limited_send, OpKind};
+use crate::retry::{with_retry, RetryPolicy};
+use crate::utils::write_bytes_atomic;
+use imagesize::ImageType;
+use mongodb::bson::doc;
+use mongodb::options::ReplaceOptions;
+use mongodb::{Collection, Database};
+use regex::Regex;
+use reqwest::header::{HeaderName, CONTENT_LENGTH, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::result::Result;
+
+const MEDIA_COLL_NAME: &str = "archived-media";
+
+lazy_static! {
+    static ref RE_MEDIA_URL: Regex = Regex::new(concat!(
+        r#"https://pbs\.twimg\.com/"#,
+        r#"(media|ext_tw_video_thumb|amplify_video_thumb|tweet_video_thumb)/[^"\s]+"#
+    ))
+    .unwrap();
+}
+
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Photo,
+    VideoThumbnail,
+}
+
+/// An image a post links to. Videos and GIFs are only ever archived as their thumbnail.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct MediaRef {
+    pub url: String,
+    pub kind: MediaKind,
+}
+
+impl MediaRef {
+    /// Media of a post from the `legacy` part of an API response.
+    pub fn from_legacy(legacy: &Value) -> Vec<Self> {
+        let media = ["extended_entities", "entities"]
+            .iter()
+            .filter_map(|x| legacy[x]["media"].as_array())
+            .find(|x| !x.is_empty());
+
+        let media = match media {
+            Some(media) => media,
+            None => return vec![],
+        };
+
+        media
+            .iter()
+            .filter_map(|x| {
+                let url = x["media_url_https"].as_str()?.to_string();
+                let kind = match x["type"].as_str() {
+                    Some("video") | Some("animated_gif") => MediaKind::VideoThumbnail,
+                    _ => MediaKind::Photo,
+                };
+
+                Some(MediaRef { url, kind })
+            })
+            .collect()
+    }
+
+    /// Media images in a rendered post. Avatars and emoji are not on the media host paths
+    /// matched here.
+    pub fn from_html(html: &str) -> Vec<Self> {
+        let mut refs: Vec<Self> = vec![];
+
+        for found in RE_MEDIA_URL.captures_iter(html) {
+            let url = found[0].replace("&amp;", "&");
+            let kind = match &found[1] {
+                "media" => MediaKind::Photo,
+                _ => MediaKind::VideoThumbnail,
+            };
+
+            if !refs.iter().any(|x| x.url == url) {
+                refs.push(MediaRef { url, kind });
+            }
+        }
+
+        refs
+    }
+}
+
+/// A downloaded media file, stored next to the post record that referenced it.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct ArchivedMedia {
+    pub url: String,
+    pub kind: MediaKind,
+    pub sha256: String,
+    pub path: String,
+    pub mime: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub bytes: u64,
+}
+
+#[derive(Debug)]
+pub struct MediaError {
+    details: String,
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for MediaError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl MediaError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = MediaError {
+            details: details.to_string(),
+        };
+
+        Box::from(err)
+    }
+}
+
+impl From<mongodb::error::Error> for Box<MediaError> {
+    fn from(e: mongodb::error::Error) -> Self {
+        MediaError::new(e.to_string().as_str())
+    }
+}
+
+impl From<std::io::Error> for Box<MediaError> {
+    fn from(e: std::io::Error) -> Self {
+        MediaError::new(e.to_string().as_str())
+    }
+}
+
+/// Downloads post media into `dir`, one file per distinct content named by its SHA-256.
+/// Files over `max_file_bytes` are skipped, and nothing new is written once the
+/// directory holds `max_total_bytes`.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct MediaArchive {
+    pub dir: String,
+    pub max_file_bytes: u64,
+    pub max_total_bytes: u64,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+impl MediaArchive {
+    /// Archives every item in `media`. Failures are logged and left out, since the post
+    /// itself is recorded either way.
+    pub async fn archive(&self, db: &Database, media: &[MediaRef]) -> Vec<ArchivedMedia> {
+        let mut archived = vec![];
+
+        // Measured once, then kept up to date as files are written.
+        let mut used = dir_size(Path::new(self.dir.as_str()));
+
+        for item in media {
+            match self.archive_one(db, item, &mut used).await {
+                Ok(Some(found)) => archived.push(found),
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!(url = item.url.as_str(), error = %e, "could not archive media");
+                }
+            }
+        }
+
+        archived
+    }
+
+    async fn archive_one(
+        &self,
+        db: &Database,
+        item: &MediaRef,
+        used: &mut u64,
+    ) -> Result<Option<ArchivedMedia>, Box<MediaError>> {
+        let collection: Collection<ArchivedMedia> = db.collection(MEDIA_COLL_NAME);
+
+        if let Some(found) = collection.find_one(doc! {"url": item.url.as_str()}, None).await? {
+            if Path::new(found.path.as_str()).exists() {
+                return Ok(Some(found));
+            }
+        }
+
+        let (content, content_type) = match self.download(item.url.as_str()).await? {
+            Some(downloaded) => downloaded,
+            None => return Ok(None),
+        };
+
+        let sha256 = format!("{:x}", Sha256::digest(&content));
+        let image_type = imagesize::image_type(&content).ok();
+        let size = imagesize::blob_size(&content).ok();
+
+        let mime = match image_type.as_ref().and_then(mime_of) {
+            Some(mime) => mime.to_string(),
+            None => content_type.unwrap_or_else(|| String::from("application/octet-stream")),
+        };
+
+        let path = self.path_for(sha256.as_str(), mime.as_str());
+
+        // Identical content from another url is already on disk.
+        if !Path::new(path.as_str()).exists() {
+            if *used + content.len() as u64 > self.max_total_bytes {
+                tracing::warn!(
+                    url = item.url.as_str(),
+                    used = *used,
+                    max_total_bytes = self.max_total_bytes,
+                    "media archive is full"
+                );
+
+                return Ok(None);
+            }
+
+            if let Some(parent) = Path::new(path.as_str()).parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            write_bytes_atomic(path.as_str(), &content)?;
+
+            *used += content.len() as u64;
+        }
+
+        let archived = ArchivedMedia {
+            url: item.url.clone(),
+            kind: item.kind,
+            sha256,
+            path,
+            mime,
+            width: size.as_ref().map(|x| x.width as u32),
+            height: size.as_ref().map(|x| x.height as u32),
+            bytes: content.len() as u64,
+        };
+
+        let options = ReplaceOptions::builder().upsert(true).build();
+
+        collection
+            .replace_one(doc! {"url": item.url.as_str()}, &archived, options)
+            .await?;
+
+        Ok(Some(archived))
+    }
+
+    /// The body and content type at `url`, or `None` when it is over `max_file_bytes`.
+    async fn download(
+        &self,
+        url: &str,
+    ) -> Result<Option<(Vec<u8>, Option<String>)>, Box<MediaError>> {
+        let client = reqwest::Client::new();
+
+        let mut res = with_retry(&self.retry, url, || {
+            limited_send(client.get(url), url, OpKind::Record)
+        })
+        .await
+        .map_err(|e| MediaError::new(e.to_string().as_str()))?;
+
+        // An error page would otherwise be archived, and deduplicated, as the file.
+        if !res.status().is_success() {
+            return Err(MediaError::new(format!("{} answered {}", url, res.status()).as_str()));
+        }
+
+        let header = |name: HeaderName| {
+            res.headers()
+                .get(name)
+                .and_then(|x| x.to_str().ok())
+                .map(|x| x.to_string())
+        };
+
+        let content_type = header(CONTENT_TYPE);
+        let announced: Option<u64> = header(CONTENT_LENGTH).and_then(|x| x.parse().ok());
+
+        if announced.is_some_and(|x| x > self.max_file_bytes) {
+            tracing::warn!(url, bytes = announced, "media file over size limit");
+
+            return Ok(None);
+        }
+
+        // The announced length may be missing or wrong, so the limit holds while reading.
+        let mut content = vec![];
+
+        while let Some(chunk) = res
+            .chunk()
+            .await
+            .map_err(|e| MediaError::new(e.to_string().as_str()))?
+        {
+            let bytes = content.len() + chunk.len();
+
+            if bytes as u64 > self.max_file_bytes {
+                tracing::warn!(url, bytes, "media file over size limit");
+
+                return Ok(None);
+            }
+
+            content.extend_from_slice(&chunk);
+        }
+
+        Ok(Some((content, content_type)))
+    }
+
+    /// `dir/ab/abcdef….ext`, fanned out by the first two hash characters.
+    pub(crate) fn path_for(&self, sha256: &str, mime: &str) -> String {
+        let ext = mime.rsplit('/').next().unwrap_or("bin");
+        let ext = if ext == "jpeg" { "jpg" } else { ext };
+
+        Path::new(self.dir.as_str())
+            .join(&sha256[..2])
+            .join(format!("{}.{}", sha256, ext))
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+pub(crate) fn mime_of(image_type: &ImageType) -> Option<&'static str> {
+    match image_type {
+        ImageType::Jpeg => Some("image/jpeg"),
+        ImageType::Png => Some("image/png"),
+        ImageType::Gif => Some("image/gif"),
+        ImageType::Webp => Some("image/webp"),
+        _ => None,
+    }
+}
+
+pub(crate) fn dir_size(dir: &Path) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    entries
+        .filter_map(|x| x.ok())
+        .map(|x| match x.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&x.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
@@ -1,8 +1,9 @@
 use crate::config::Config;
-use crate::utils::write_strings_to_zip;
-use std::path::Path;
+use crate::utils::{bot_error, write_strings_to_zip};
+use std::path::{Path, PathBuf};
 use serde::{Serialize, Deserialize};
 use serde_json::from_str;
+use tempfile::TempDir;
 use thirtyfour::{prelude::*, ChromeCapabilities};
 
 lazy_static! {
@@ -55,7 +56,7 @@ impl Proxy {
         ret
     }
 
-    fn create_ext(&self) {
+    fn create_ext(&self, dir: &Path) -> zip::result::ZipResult<PathBuf> {
         let background = format!(
             r#"
         var config = {{
@@ -90,36 +91,34 @@ impl Proxy {
             self.host, self.username, self.password
         );
 
-        write_strings_to_zip(
-            format!("./{}-{}.crx", self.host, self.username),
-            background,
-            MANIFEST.clone(),
-        )
-        .unwrap()
+        let path = dir.join(format!("{}-{}.crx", self.host, self.username));
+
+        write_strings_to_zip(path.to_string_lossy().to_string(), background, MANIFEST.clone())?;
+
+        Ok(path)
     }
 
+    /// The extension is written to a temp dir that is removed when this returns, whether
+    /// or not the launch worked.
     pub async fn launch_driver_with_proxy(&self, config: Config) -> WebDriverResult<WebDriver> {
-        let proxy = Self::new(
-            self.host.clone(),
-            self.username.clone(),
-            self.password.clone(),
-        );
-
         let mut caps = ChromeCapabilities::new();
 
         caps.add_chrome_option("user-agent", USER_AGENT.clone())?;
 
-        proxy.create_ext();
-        let ext_str = format!("{}-{}.crx", proxy.host, proxy.username);
-        let extension_path = Path::new(&ext_str);
+        let ext_dir = TempDir::new().map_err(|e| bot_error(e.to_string()))?;
+        let extension_path = self
+            .create_ext(ext_dir.path())
+            .map_err(|e| bot_error(e.to_string()))?;
 
-        caps.add_extension(extension_path)?;
+        caps.add_extension(extension_path.as_path())?;
 
         let driver = WebDriver::new(&config.selenium_url, caps).await?;
 
-        config.apply_config(&driver).await?;
+        if let Err(e) = config.apply_config(&driver).await {
+            let _ = driver.quit().await;
 
-        std::fs::remove_file(ext_str).unwrap();
+            return Err(e);
+        }
 
         Ok(driver)
     }
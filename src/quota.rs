@@ -0,0 +1,355 @@
+use crate::action::Action;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use mongodb::bson::{doc, Document};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{IndexOptions, UpdateOptions};
+use mongodb::{Collection, Database, IndexModel};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::result::Result;
+use tokio::sync::Mutex;
+
+const QUOTA_COLL_NAME: &str = "action-quotas";
+const DUPLICATE_KEY: i32 = 11000;
+
+lazy_static! {
+    /// Whether the unique counter index was created yet in this process.
+    static ref INDEXED: Mutex<bool> = Mutex::new(false);
+}
+
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+pub enum QuotaKind {
+    Posts,
+    Replies,
+    Likes,
+    Retweets,
+    Searches,
+    RecordFetches,
+}
+
+impl QuotaKind {
+    pub fn of(action: &Action) -> Self {
+        match action {
+            Action::PostText(_) | Action::PostImage(_) | Action::QuoteRetweet(_) => {
+                QuotaKind::Posts
+            }
+            Action::CommentText(_) | Action::CommentImage(_) => QuotaKind::Replies,
+            Action::LikePost(_) => QuotaKind::Likes,
+            Action::Retweet(_) => QuotaKind::Retweets,
+            Action::SearchTwitter(_) => QuotaKind::Searches,
+            Action::RecordPost(_) | Action::Capture(_) => QuotaKind::RecordFetches,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+pub enum QuotaWindow {
+    Hour,
+    Day,
+}
+
+impl QuotaWindow {
+    fn length(&self) -> Duration {
+        match self {
+            QuotaWindow::Hour => Duration::hours(1),
+            QuotaWindow::Day => Duration::days(1),
+        }
+    }
+
+    /// Start of the window `at` falls in. Windows are aligned to UTC.
+    pub fn start(&self, at: DateTime<Utc>) -> DateTime<Utc> {
+        at.duration_trunc(self.length()).unwrap()
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct QuotaLimit {
+    pub kind: QuotaKind,
+    pub per_hour: Option<u32>,
+    pub per_day: Option<u32>,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum OverQuota {
+    /// Wait for the window to reset, then run.
+    Defer,
+    /// Skip the run.
+    Drop,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct QuotaConfig {
+    pub limits: Vec<QuotaLimit>,
+    pub over_quota: OverQuota,
+}
+
+impl QuotaConfig {
+    /// The tightest limit on `kind` in `window`, if any.
+    pub fn limit_for(&self, kind: QuotaKind, window: QuotaWindow) -> Option<u32> {
+        self.limits
+            .iter()
+            .filter(|x| x.kind == kind)
+            .filter_map(|x| match window {
+                QuotaWindow::Hour => x.per_hour,
+                QuotaWindow::Day => x.per_day,
+            })
+            .min()
+    }
+
+    /// The first window whose `used` count leaves no room for another run of `kind`.
+    pub fn over_limit(
+        &self,
+        kind: QuotaKind,
+        used: &[(QuotaWindow, u32)],
+        at: DateTime<Utc>,
+    ) -> Option<QuotaExceeded> {
+        used.iter().find_map(|(window, used)| match self.limit_for(kind, *window) {
+            Some(max) if *used >= max => Some(exceeded(kind, *window, max, at)),
+            _ => None,
+        })
+    }
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        QuotaConfig {
+            limits: vec![],
+            over_quota: OverQuota::Defer,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct QuotaCounter {
+    pub account: String,
+    pub kind: QuotaKind,
+    pub window: QuotaWindow,
+    pub window_start: String,
+    pub count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub kind: QuotaKind,
+    pub window: QuotaWindow,
+    pub limit: u32,
+    pub resets_at: DateTime<Utc>,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} quota of {} per {:?} used up until {}",
+            self.kind, self.limit, self.window, self.resets_at
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct QuotaError {
+    details: String,
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for QuotaError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl QuotaError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = QuotaError {
+            details: details.to_string(),
+        };
+
+        Box::from(err)
+    }
+}
+
+impl From<mongodb::error::Error> for Box<QuotaError> {
+    fn from(e: mongodb::error::Error) -> Self {
+        QuotaError::new(e.to_string().as_str())
+    }
+}
+
+pub struct Quotas {
+    counters: Collection<QuotaCounter>,
+}
+
+impl Quotas {
+    pub fn new(db: &Database) -> Self {
+        Quotas {
+            counters: db.collection::<QuotaCounter>(QUOTA_COLL_NAME),
+        }
+    }
+
+    async fn used(
+        &self,
+        account: &str,
+        kind: QuotaKind,
+        window: QuotaWindow,
+        at: DateTime<Utc>,
+    ) -> Result<u32, Box<QuotaError>> {
+        let filter = counter_filter(account, kind, window, at);
+
+        let found = self.counters.find_one(filter, None).await?;
+
+        Ok(found.map(|x| x.count).unwrap_or(0))
+    }
+
+    /// Returns the first limit `action` would go over if it ran `at`. Only a hint for
+    /// waiting; `reserve` is what holds the limit.
+    pub async fn check(
+        &self,
+        config: &QuotaConfig,
+        account: &str,
+        action: &Action,
+        at: DateTime<Utc>,
+    ) -> Result<Option<QuotaExceeded>, Box<QuotaError>> {
+        let kind = QuotaKind::of(action);
+        let mut used = vec![];
+
+        for window in [QuotaWindow::Hour, QuotaWindow::Day] {
+            if config.limit_for(kind, window).is_some() {
+                used.push((window, self.used(account, kind, window, at).await?));
+            }
+        }
+
+        Ok(config.over_limit(kind, &used, at))
+    }
+
+    /// One unique counter per account, kind and window, so a reservation that finds the
+    /// counter full cannot upsert a second one next to it.
+    async fn ensure_index(&self) -> Result<(), Box<QuotaError>> {
+        let mut indexed = INDEXED.lock().await;
+
+        if !*indexed {
+            let index = IndexModel::builder()
+                .keys(doc! {"account": 1, "kind": 1, "window": 1, "window_start": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build();
+
+            self.counters.create_index(index, None).await?;
+            *indexed = true;
+        }
+
+        Ok(())
+    }
+
+    /// Takes a slot for one run of `action` in both the hourly and the daily window, or
+    /// returns the limit it would go over. Each window is a single conditional `$inc`,
+    /// so bots sharing an account cannot both take the last slot.
+    pub async fn reserve(
+        &self,
+        config: &QuotaConfig,
+        account: &str,
+        action: &Action,
+        at: DateTime<Utc>,
+    ) -> Result<Option<QuotaExceeded>, Box<QuotaError>> {
+        self.ensure_index().await?;
+
+        let kind = QuotaKind::of(action);
+        let options = UpdateOptions::builder().upsert(true).build();
+        let mut taken = vec![];
+
+        for window in [QuotaWindow::Hour, QuotaWindow::Day] {
+            let mut filter = counter_filter(account, kind, window, at);
+            let max = config.limit_for(kind, window);
+
+            if let Some(max) = max {
+                filter.insert("count", doc! {"$lt": max});
+            }
+
+            let result = self
+                .counters
+                .update_one(filter, doc! {"$inc": {"count": 1}}, options.clone())
+                .await;
+
+            match (result, max) {
+                (Ok(_), _) => taken.push(window),
+                (Err(e), Some(max)) if is_duplicate_key(&e) => {
+                    self.give_back(account, kind, &taken, at).await?;
+
+                    return Ok(Some(exceeded(kind, window, max, at)));
+                }
+                (Err(e), _) => {
+                    self.give_back(account, kind, &taken, at).await?;
+
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns a slot `reserve` took at `at`, for a run that did not go through.
+    pub async fn release(
+        &self,
+        account: &str,
+        action: &Action,
+        at: DateTime<Utc>,
+    ) -> Result<(), Box<QuotaError>> {
+        let windows = [QuotaWindow::Hour, QuotaWindow::Day];
+
+        self.give_back(account, QuotaKind::of(action), &windows, at).await
+    }
+
+    async fn give_back(
+        &self,
+        account: &str,
+        kind: QuotaKind,
+        windows: &[QuotaWindow],
+        at: DateTime<Utc>,
+    ) -> Result<(), Box<QuotaError>> {
+        for window in windows {
+            let mut filter = counter_filter(account, kind, *window, at);
+            filter.insert("count", doc! {"$gt": 0});
+
+            self.counters
+                .update_one(filter, doc! {"$inc": {"count": -1}}, None)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn exceeded(kind: QuotaKind, window: QuotaWindow, limit: u32, at: DateTime<Utc>) -> QuotaExceeded {
+    QuotaExceeded {
+        kind,
+        window,
+        limit,
+        resets_at: window.start(at) + window.length(),
+    }
+}
+
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    matches!(
+        e.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(x)) if x.code == DUPLICATE_KEY
+    )
+}
+
+fn counter_filter(
+    account: &str,
+    kind: QuotaKind,
+    window: QuotaWindow,
+    at: DateTime<Utc>,
+) -> Document {
+    doc! {
+        "account": account,
+        "kind": format!("{:?}", kind),
+        "window": format!("{:?}", window),
+        "window_start": window.start(at).to_rfc3339(),
+    }
+}
@@ -0,0 +1,336 @@
+use crate::retry::Retryable;
+use crate::utils::bot_error;
+use chrono::{NaiveDate, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::result::Result;
+use thirtyfour::prelude::*;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+const MAX_THROTTLED_RETRIES: u32 = 5;
+const BASE_BACKOFF_SECS: u64 = 30;
+
+lazy_static! {
+    static ref LIMITER: Mutex<RateLimiter> = Mutex::new(RateLimiter::new(Default::default()));
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub enum OpKind {
+    Post,
+    Engage,
+    Search,
+    Record,
+    Api,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct BucketLimit {
+    pub capacity: u32,
+    pub refill_per_minute: u32,
+    pub daily_cap: Option<u32>,
+}
+
+impl Default for BucketLimit {
+    fn default() -> Self {
+        BucketLimit {
+            capacity: 3,
+            refill_per_minute: 6,
+            daily_cap: None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct RateRule {
+    pub host: Option<String>,
+    pub op: Option<OpKind>,
+    pub limit: BucketLimit,
+}
+
+#[derive(Serialize, Clone, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub default: BucketLimit,
+    #[serde(default)]
+    pub rules: Vec<RateRule>,
+}
+
+impl RateLimitConfig {
+    fn limit_for(&self, host: &str, op: &OpKind) -> BucketLimit {
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.host.as_ref().is_none_or(|h| h == host)
+                    && rule.op.as_ref().is_none_or(|o| o == op)
+            })
+            .map(|rule| rule.limit.clone())
+            .unwrap_or_else(|| self.default.clone())
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimitError {
+    details: String,
+    transient: bool,
+}
+
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for RateLimitError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl RateLimitError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = RateLimitError {
+            details: details.to_string(),
+            transient: false,
+        };
+
+        Box::from(err)
+    }
+
+    pub fn transient(details: &str) -> Box<Self> {
+        let err = RateLimitError {
+            details: details.to_string(),
+            transient: true,
+        };
+
+        Box::from(err)
+    }
+}
+
+impl Retryable for RateLimitError {
+    fn is_retryable(&self) -> bool {
+        self.transient
+    }
+}
+
+struct Bucket {
+    tokens: u32,
+    last_refill: Instant,
+    day: NaiveDate,
+    used_today: u32,
+    blocked_until: Option<Instant>,
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: HashMap<(String, OpKind), Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Runs on every browser launch. The buckets outlive it, so a relaunch does not reset
+    /// daily caps or Retry-After blocks; tokens are only cut down to a smaller capacity.
+    pub async fn configure(config: RateLimitConfig) {
+        LIMITER.lock().await.reconfigure(config);
+    }
+
+    pub(crate) fn reconfigure(&mut self, config: RateLimitConfig) {
+        if self.config == config {
+            return;
+        }
+
+        for ((host, op), bucket) in self.buckets.iter_mut() {
+            bucket.tokens = bucket.tokens.min(config.limit_for(host, op).capacity);
+        }
+
+        self.config = config;
+    }
+
+    /// Takes a token for `host`/`op`, or returns how long to wait before asking again.
+    pub(crate) fn try_take(
+        &mut self,
+        host: &str,
+        op: &OpKind,
+    ) -> Result<Option<Duration>, Box<RateLimitError>> {
+        let limit = self.config.limit_for(host, op);
+        let now = Instant::now();
+        let today = Utc::now().date_naive();
+
+        let bucket = self
+            .buckets
+            .entry((host.to_string(), op.clone()))
+            .or_insert(Bucket {
+                tokens: limit.capacity,
+                last_refill: now,
+                day: today,
+                used_today: 0,
+                blocked_until: None,
+            });
+
+        if bucket.day != today {
+            bucket.day = today;
+            bucket.used_today = 0;
+        }
+
+        if let Some(cap) = limit.daily_cap {
+            if bucket.used_today >= cap {
+                return Err(RateLimitError::new(
+                    format!("daily cap of {} reached for {:?} on {}", cap, op, host).as_str(),
+                ));
+            }
+        }
+
+        if let Some(until) = bucket.blocked_until {
+            if until > now {
+                return Ok(Some(until - now));
+            }
+
+            bucket.blocked_until = None;
+        }
+
+        let per_token = Duration::from_secs(60) / limit.refill_per_minute.max(1);
+        let earned = (now - bucket.last_refill).as_millis() / per_token.as_millis().max(1);
+
+        if earned > 0 {
+            bucket.tokens = (bucket.tokens + earned as u32).min(limit.capacity);
+            bucket.last_refill += per_token * earned as u32;
+        }
+
+        if bucket.tokens == 0 {
+            return Ok(Some(per_token - (now - bucket.last_refill)));
+        }
+
+        bucket.tokens -= 1;
+        bucket.used_today += 1;
+
+        Ok(None)
+    }
+
+    pub async fn acquire(host: &str, op: OpKind) -> Result<(), Box<RateLimitError>> {
+        loop {
+            let wait = LIMITER.lock().await.try_take(host, &op)?;
+
+            match wait {
+                Some(wait) => sleep(wait).await,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    pub async fn backoff(host: &str, op: OpKind, wait: Duration) {
+        let mut limiter = LIMITER.lock().await;
+
+        if let Some(bucket) = limiter.buckets.get_mut(&(host.to_string(), op)) {
+            bucket.blocked_until = Some(Instant::now() + wait);
+            bucket.tokens = 0;
+        }
+    }
+}
+
+pub fn host_of(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(parsed) => parsed.host_str().unwrap_or_default().to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+/// Reads `Retry-After` or `x-rate-limit-reset`, whichever the server sent.
+pub fn throttle_wait(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(secs) = headers
+        .get(RETRY_AFTER)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.trim().parse::<u64>().ok())
+    {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let remaining = headers
+        .get("x-rate-limit-remaining")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.trim().parse::<u64>().ok());
+
+    let reset = headers
+        .get("x-rate-limit-reset")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.trim().parse::<i64>().ok());
+
+    match (remaining, reset) {
+        (Some(0), Some(reset)) | (None, Some(reset)) => {
+            let secs = (reset - Utc::now().timestamp()).max(1);
+
+            Some(Duration::from_secs(secs as u64))
+        }
+        _ => None,
+    }
+}
+
+pub async fn limited_get(driver: &WebDriver, url: String, op: OpKind) -> WebDriverResult<()> {
+    RateLimiter::acquire(host_of(url.as_str()).as_str(), op)
+        .await
+        .map_err(|e| bot_error(e.to_string()))?;
+
+    driver.get(url).await?;
+
+    Ok(())
+}
+
+pub async fn limited_send(
+    request: RequestBuilder,
+    url: &str,
+    op: OpKind,
+) -> Result<Response, Box<RateLimitError>> {
+    let host = host_of(url);
+
+    for attempt in 0..MAX_THROTTLED_RETRIES {
+        RateLimiter::acquire(host.as_str(), op.clone()).await?;
+
+        let attempt_request = match request.try_clone() {
+            Some(r) => r,
+            None => return Err(RateLimitError::new("request body cannot be retried")),
+        };
+
+        let res = attempt_request.send().await.map_err(|e| {
+            if e.is_timeout() || e.is_connect() || e.is_request() {
+                RateLimitError::transient(e.to_string().as_str())
+            } else {
+                RateLimitError::new(e.to_string().as_str())
+            }
+        })?;
+
+        let wait = throttle_wait(res.headers());
+
+        if res.status() == StatusCode::TOO_MANY_REQUESTS {
+            let wait = wait.unwrap_or(Duration::from_secs(BASE_BACKOFF_SECS << attempt));
+
+            RateLimiter::backoff(host.as_str(), op.clone(), wait).await;
+
+            continue;
+        }
+
+        if let (Some(wait), Some("0")) = (
+            wait,
+            res.headers()
+                .get("x-rate-limit-remaining")
+                .and_then(|x| x.to_str().ok()),
+        ) {
+            RateLimiter::backoff(host.as_str(), op.clone(), wait).await;
+        }
+
+        return Ok(res);
+    }
+
+    Err(RateLimitError::new(
+        format!("{} kept answering 429", host).as_str(),
+    ))
+}
@@ -1,12 +1,13 @@
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use std::cell::RefCell;
+use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::task;
 
 use mongodb::Database;
-use thirtyfour::WebDriver;
 
-use crate::{config::Behavior, cronueue::CronueueAction};
+use crate::session::SessionManager;
+use crate::{cancel::JobControl, config::Config, cronueue::CronueueAction};
 
 #[derive(Clone)]
 pub struct CronChannel {
@@ -14,37 +15,36 @@ pub struct CronChannel {
     cronueue_action: CronueueAction,
     sender: Sender<u32>,
     receiver: Receiver<u32>,
+    control: JobControl,
 }
 
 impl CronChannel {
-    pub fn new(name_raw: String, cronueue_action: CronueueAction) -> Self {
+    pub fn new(name: String, cronueue_action: CronueueAction, control: JobControl) -> Self {
         let (tx, rx) = unbounded();
 
-        let name_clone = name_raw.clone();
-        let mut name = name_clone;
-        name.push_str("-queue");
-
         CronChannel {
             name,
             cronueue_action,
             sender: tx,
             receiver: rx,
+            control,
         }
     }
 
-    #[tokio::main]
-    pub async fn launch(
+    /// Spawns the job onto the current runtime. The session, config and database are
+    /// shared with every other job.
+    pub fn launch(
         this: Mutex<Self>,
-        driver: Mutex<WebDriver>,
-        behavior: Mutex<Behavior>,
-        db: Mutex<Database>,
+        session: Arc<Mutex<SessionManager>>,
+        config: Arc<Mutex<Config>>,
+        db: Arc<Mutex<Database>>,
     ) {
         task::spawn(async move {
             let self_ = this.lock().await;
 
             self_
                 .cronueue_action
-                .run_queue(driver, behavior, db, &self_.receiver)
+                .run_queue(&self_.name, session, config, db, &self_.receiver, &self_.control)
                 .await
                 .unwrap();
         });
@@ -54,7 +54,16 @@ impl CronChannel {
         self.sender.send(millis).unwrap();
     }
 
+    pub fn pause(&self) {
+        self.control.pause();
+    }
+
+    pub fn resume(&self) {
+        self.control.resume();
+    }
+
     pub fn terminate(&self) {
+        self.control.cancel();
         self.sender.send(0).unwrap();
     }
 }
@@ -70,8 +79,8 @@ impl ReadWriteQueue {
         ReadWriteQueue(cell)
     }
 
-    pub fn add_new_action(&self, name: String, action: CronueueAction) {
-        let chan_cron = CronChannel::new(name, action);
+    pub fn add_new_action(&self, name: String, action: CronueueAction, control: JobControl) {
+        let chan_cron = CronChannel::new(name, action, control);
 
         let ReadWriteQueue(cell) = self;
 
@@ -82,9 +91,9 @@ impl ReadWriteQueue {
 
     pub fn launch_lastest(
         &self,
-        driver: Mutex<WebDriver>,
-        behavior: Mutex<Behavior>,
-        db: Mutex<Database>,
+        session: Arc<Mutex<SessionManager>>,
+        config: Arc<Mutex<Config>>,
+        db: Arc<Mutex<Database>>,
     ) {
         let ReadWriteQueue(cell) = self;
 
@@ -93,26 +102,26 @@ impl ReadWriteQueue {
         if let Some(last) = r.clone().into_iter().last() {
             let this = Mutex::new(last);
 
-            CronChannel::launch(this, driver, behavior, db);
+            CronChannel::launch(this, session, config, db);
         }
     }
 
-    pub async fn launch_name(
+    pub fn launch_name(
         &self,
         name: String,
-        driver: Mutex<WebDriver>,
-        behavior: Mutex<Behavior>,
-        db: Mutex<Database>,
+        session: Arc<Mutex<SessionManager>>,
+        config: Arc<Mutex<Config>>,
+        db: Arc<Mutex<Database>>,
     ) {
         let ReadWriteQueue(cell) = self;
 
         let r = cell.borrow();
 
-        for w in r.clone().into_iter().next() {
+        for w in r.clone().into_iter() {
             if w.name == name {
                 let this = Mutex::new(w);
 
-                CronChannel::launch(this, driver, behavior, db);
+                CronChannel::launch(this, session, config, db);
 
                 break;
             }
@@ -143,6 +152,34 @@ impl ReadWriteQueue {
         }
     }
 
+    pub fn pause_name(&self, name: String) {
+        let ReadWriteQueue(cell) = self;
+
+        let r = cell.borrow();
+
+        for w in r.clone().into_iter() {
+            if w.name == name {
+                w.pause();
+
+                break;
+            }
+        }
+    }
+
+    pub fn resume_name(&self, name: String) {
+        let ReadWriteQueue(cell) = self;
+
+        let r = cell.borrow();
+
+        for w in r.clone().into_iter() {
+            if w.name == name {
+                w.resume();
+
+                break;
+            }
+        }
+    }
+
     pub fn terminate_latest(&self) {
         let ReadWriteQueue(cell) = self;
 
@@ -166,4 +203,31 @@ impl ReadWriteQueue {
             }
         }
     }
+
+    pub fn names(&self) -> Vec<String> {
+        let ReadWriteQueue(cell) = self;
+
+        cell.borrow().iter().map(|x| x.name.clone()).collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        let ReadWriteQueue(cell) = self;
+
+        cell.borrow().iter().any(|x| x.name == name)
+    }
+
+    pub fn action_of(&self, name: &str) -> Option<CronueueAction> {
+        let ReadWriteQueue(cell) = self;
+
+        cell.borrow().iter().find(|x| x.name == name).map(|x| x.cronueue_action.clone())
+    }
+
+    /// Stops the job and forgets it, so the name can be used again.
+    pub fn remove_name(&self, name: String) {
+        self.terminate_name(name.clone());
+
+        let ReadWriteQueue(cell) = self;
+
+        cell.borrow_mut().retain(|x| x.name != name);
+    }
 }
@@ -1,21 +1,42 @@
-use crate::utils::{make_get_post_url, today_date_coll_name};
+use crate::failure::watch_console;
+use crate::media::{ArchivedMedia, MediaArchive, MediaRef};
+use crate::rate_limit::{limited_get, limited_send, OpKind};
+use crate::retry::{with_retry, RetryPolicy};
+use crate::utils::{date_coll_name, make_get_post_url, today_date_coll_name};
 use async_trait::async_trait;
-use futures::executor::block_on;
-use mongodb::bson::{doc, Document};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, to_bson, Document};
+use mongodb::options::{ReplaceOptions, UpdateOptions};
 use mongodb::Database;
 use regex::Regex;
 use reqwest::header::*;
 use serde::{Deserialize, Serialize};
-use serde_json::from_str;
+use serde_json::{from_str, Value};
 use std::error::Error;
 use std::fmt;
 use std::result::Result;
 use thirtyfour::prelude::*;
 use tokio::time::{sleep, Duration};
 
+const PROGRESS_COLL_NAME: &str = "record-progress";
+const MARK_COLL_NAME: &str = "record-marks";
+const ARTICLE_XPATH: &str = r#"//article[@data-testid="tweet"]"#;
+const MEDIA_TEST_IDS: [&str; 2] = [r#"data-testid="tweetPhoto""#, r#"data-testid="videoPlayer""#];
+const SOCIAL_CONTEXT_ID: &str = r#"data-testid="socialContext""#;
+const TEST_ID_ATTR: &str = "data-testid=";
+const SCROLL_SCRIPT: &str = "window.scrollTo(0, document.body.scrollHeight);";
+const SCROLL_WAIT: Duration = Duration::from_millis(1500);
+const MAX_SCROLLS: u32 = 20;
+const STALLED_SCROLLS: u32 = 3;
+const LOAD_POLL: Duration = Duration::from_millis(500);
+const LOAD_POLLS: u32 = 30;
+
 lazy_static! {
-    static ref RE_POST: Regex = Regex::new(r#""__typename":"Tweet","rest_id":"\d+""#).unwrap();
-    static ref RE_USER_NAME: Regex = Regex::new(r#""screen_name":"([A-Za-z0-9\_]+)""#).unwrap();
+    static ref RE_PERMALINK: Regex =
+        Regex::new(r#"<a[^>]*href="([^"]*/status/\d+)"[^>]*>\s*<time[^>]*datetime="([^"]+)""#)
+            .unwrap();
+    static ref RE_TAG: Regex = Regex::new(r#"<[^>]+>"#).unwrap();
 }
 
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
@@ -24,6 +45,253 @@ pub enum RecordMode {
     LastFive,
     LastTen,
     AllFound,
+    Select(RecordSelector),
+}
+
+impl RecordMode {
+    pub fn selector(&self) -> RecordSelector {
+        let limit = |n| RecordSelector {
+            limit: Some(n),
+            ..Default::default()
+        };
+
+        match self {
+            RecordMode::Last => limit(1),
+            RecordMode::LastFive => limit(5),
+            RecordMode::LastTen => limit(10),
+            RecordMode::AllFound => RecordSelector::default(),
+            RecordMode::Select(selector) => selector.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Deserialize, Default, Debug, PartialEq, Eq)]
+pub enum Inclusion {
+    #[default]
+    Include,
+    Exclude,
+    Only,
+}
+
+impl Inclusion {
+    fn allows(&self, flag: bool) -> bool {
+        match self {
+            Inclusion::Include => true,
+            Inclusion::Exclude => !flag,
+            Inclusion::Only => flag,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct PostFilters {
+    #[serde(default)]
+    pub replies: Inclusion,
+    #[serde(default)]
+    pub retweets: Inclusion,
+    #[serde(default)]
+    pub quotes: Inclusion,
+    #[serde(default)]
+    pub media: Inclusion,
+}
+
+#[derive(Serialize, Clone, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct RecordSelector {
+    pub limit: Option<usize>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Only posts newer than the newest one recorded from this timeline before.
+    #[serde(default)]
+    pub newer_than_last: bool,
+    #[serde(default)]
+    pub filters: PostFilters,
+}
+
+impl RecordSelector {
+    fn is_newer(&self, post: &TimelinePost, last_recorded: Option<u64>) -> bool {
+        match (self.newer_than_last, last_recorded, post.id_number()) {
+            (true, Some(last), Some(id)) => id > last,
+            _ => true,
+        }
+    }
+
+    pub fn matches(&self, post: &TimelinePost, last_recorded: Option<u64>) -> bool {
+        let after_since = match (self.since, post.created_at) {
+            (Some(since), Some(at)) => at >= since,
+            _ => true,
+        };
+        let before_until = match (self.until, post.created_at) {
+            (Some(until), Some(at)) => at <= until,
+            _ => true,
+        };
+
+        after_since
+            && before_until
+            && self.is_newer(post, last_recorded)
+            && self.filters.replies.allows(post.is_reply)
+            && self.filters.retweets.allows(post.is_retweet)
+            && self.filters.quotes.allows(post.is_quote)
+            && self.filters.media.allows(post.has_media)
+    }
+
+    /// Timelines run newest first, so once a post is too old nothing after it can match.
+    /// Pinned posts sit out of order and never end the walk.
+    fn is_past(&self, post: &TimelinePost, last_recorded: Option<u64>) -> bool {
+        if post.is_pinned {
+            return false;
+        }
+
+        let before_since = matches!(
+            (self.since, post.created_at),
+            (Some(since), Some(at)) if at < since
+        );
+
+        before_since || !self.is_newer(post, last_recorded)
+    }
+
+    /// Picks the matching posts out of one page. `already` counts posts taken from
+    /// earlier pages; the flag is set once no later page can add anything.
+    pub fn select(
+        &self,
+        posts: Vec<TimelinePost>,
+        last_recorded: Option<u64>,
+        already: usize,
+    ) -> (Vec<TimelinePost>, bool) {
+        let mut selected = vec![];
+
+        for post in posts {
+            if self.limit.is_some_and(|x| already + selected.len() >= x) {
+                return (selected, true);
+            }
+
+            if self.is_past(&post, last_recorded) {
+                return (selected, true);
+            }
+
+            if self.matches(&post, last_recorded) {
+                selected.push(post);
+            }
+        }
+
+        let full = self.limit.is_some_and(|x| already + selected.len() >= x);
+
+        (selected, full)
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+struct RecordMark {
+    timeline: String,
+    last_post_id: String,
+}
+
+/// Id of the newest post recorded from `timeline` so far.
+pub async fn last_recorded(db: &Database, timeline: &str) -> Option<u64> {
+    let found = db
+        .collection::<RecordMark>(MARK_COLL_NAME)
+        .find_one(doc! {"timeline": timeline}, None)
+        .await;
+
+    match found {
+        Ok(mark) => mark.and_then(|x| x.last_post_id.parse().ok()),
+        Err(e) => {
+            tracing::warn!(timeline, error = %e, "could not read last recorded post");
+
+            None
+        }
+    }
+}
+
+async fn mark_recorded(db: &Database, timeline: &str, posts: &[TimelinePost]) {
+    let newest = match posts.iter().filter_map(|x| x.id_number()).max() {
+        Some(newest) => newest,
+        None => return,
+    };
+
+    if last_recorded(db, timeline).await.is_some_and(|x| x >= newest) {
+        return;
+    }
+
+    let mark = RecordMark {
+        timeline: timeline.to_string(),
+        last_post_id: newest.to_string(),
+    };
+    let options = ReplaceOptions::builder().upsert(true).build();
+
+    if let Err(e) = db
+        .collection::<RecordMark>(MARK_COLL_NAME)
+        .replace_one(doc! {"timeline": timeline}, &mark, options)
+        .await
+    {
+        tracing::warn!(timeline, error = %e, "could not save last recorded post");
+    }
+}
+
+/// Upserts `posts` into today's collection. With an `archive`, their media is downloaded
+/// first and described on the post record.
+async fn store_posts(
+    db: &Database,
+    posts: &[TimelinePost],
+    archive: Option<&MediaArchive>,
+) -> Result<(), Box<DBInsertError>> {
+    let collection = db.collection::<Document>(&today_date_coll_name());
+    let options = UpdateOptions::builder().upsert(true).build();
+
+    for post in posts {
+        let filter = doc! {"username": post.username.as_str(), "post": post.post_id.as_str()};
+        let mut record = filter.clone();
+
+        if let Some(archive) = archive.filter(|_| !post.media.is_empty()) {
+            let archived = archive.archive(db, &post.media).await;
+
+            let archived =
+                to_bson(&archived).map_err(|e| DBInsertError::new(e.to_string().as_str()))?;
+
+            record.insert("media", archived);
+        }
+
+        collection
+            .update_one(filter, doc! {"$set": record}, options.clone())
+            .await
+            .map_err(|e| DBInsertError::new(e.to_string().as_str()))?;
+    }
+
+    Ok(())
+}
+
+/// A post as `store_posts` left it in a day collection.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct RecordedPost {
+    pub username: String,
+    pub post: String,
+    #[serde(default)]
+    pub media: Vec<ArchivedMedia>,
+}
+
+/// Everything recorded on the days from `from` to `to`, both included.
+pub async fn recorded_posts(
+    db: &Database,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<RecordedPost>, Box<DBInsertError>> {
+    let mut posts = vec![];
+
+    for day in from.iter_days().take_while(|x| *x <= to) {
+        let cursor = db
+            .collection::<RecordedPost>(&date_coll_name(day))
+            .find(None, None)
+            .await
+            .map_err(|e| DBInsertError::new(e.to_string().as_str()))?;
+
+        let found: Vec<RecordedPost> = cursor
+            .try_collect()
+            .await
+            .map_err(|e| DBInsertError::new(e.to_string().as_str()))?;
+
+        posts.extend(found);
+    }
+
+    Ok(posts)
 }
 
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
@@ -39,6 +307,10 @@ pub struct PostRecordScrape {
     profile_url: String,
     record_mode: RecordMode,
     tweet_type: TweetType,
+    #[serde(default)]
+    max_scrolls: Option<u32>,
+    #[serde(default)]
+    media_archive: Option<MediaArchive>,
 }
 
 #[derive(Debug)]
@@ -83,113 +355,143 @@ impl PostRecordScrape {
             profile_url,
             record_mode,
             tweet_type,
+            max_scrolls: None,
+            media_archive: None,
         }
     }
 
-    pub async fn get_posts(&self, driver: &WebDriver) -> WebDriverResult<Vec<String>> {
-        driver.get(self.profile_url.clone()).await?;
-        sleep(Duration::from_millis(8000)).await;
+    pub fn tab_url(&self) -> String {
+        tab_url(self.profile_url.as_str(), &self.tweet_type)
+    }
 
-        let scrolldown_script = r#"
-            setInterva(() => {
-                window.scroll(0, Math.random() * window.innerHeight);
-            }, 100)
-        "#;
+    /// Opens the tab for `tweet_type` and scrolls until the record mode has what it
+    /// wants, the timeline stops growing or `max_scrolls` runs out.
+    pub async fn get_posts(
+        &self,
+        driver: &WebDriver,
+        last_recorded: Option<u64>,
+    ) -> WebDriverResult<Vec<TimelinePost>> {
+        limited_get(driver, self.tab_url(), OpKind::Record).await?;
+        watch_console(driver).await;
 
-        driver.execute_script(scrolldown_script).await?;
+        wait_for_articles(driver).await?;
 
-        sleep(Duration::from_millis(500)).await;
+        let selector = self.record_mode.selector();
+        let max_scrolls = self.max_scrolls.unwrap_or(MAX_SCROLLS);
 
-        let mut posts = Vec::<String>::new();
+        let mut posts: Vec<TimelinePost> = vec![];
+        let mut stalled = 0;
 
-        let has_pinned: usize;
+        for _ in 0..=max_scrolls {
+            let before = posts.len();
 
-        match driver
-            .find_element(By::XPath("//span[text() = \"Pinned Tweet\"]"))
-            .await
-        {
-            Ok(_) => {
-                has_pinned = 1;
-            }
-            Err(_) => {
-                has_pinned = 0;
-            }
-        }
+            // The timeline drops posts scrolled far out of view, so gather as we go.
+            for article in driver.find_elements(By::XPath(ARTICLE_XPATH)).await? {
+                let html = match article.outer_html().await {
+                    Ok(html) => html,
+                    Err(e) => {
+                        tracing::debug!(error = %e, "skipping post that could not be read");
 
-        let href_click_text: String;
-        let mut username = String::new();
-        if let Some(un) = self.profile_url.split("/").last() {
-            username = un.to_string();
-        }
+                        continue;
+                    }
+                };
 
-        match self.tweet_type {
-            TweetType::Reply => {
-                href_click_text = format!("/{}/with_replies", username);
-            }
-            TweetType::Post => {
-                href_click_text = format!("/{}", username);
+                if let Some(post) = parse_article(html.as_str()) {
+                    if !posts.iter().any(|x| x.post_id == post.post_id) {
+                        posts.push(post);
+                    }
+                }
             }
-            TweetType::Media => {
-                href_click_text = format!("/{}/media", username);
+
+            if selector.select(posts.clone(), last_recorded, 0).1 {
+                break;
             }
-            TweetType::Likes => {
-                href_click_text = format!("/{}/likes", username);
+
+            stalled = if posts.len() == before { stalled + 1 } else { 0 };
+
+            if stalled >= STALLED_SCROLLS {
+                break;
             }
+
+            driver.execute_script(SCROLL_SCRIPT).await?;
+            sleep(SCROLL_WAIT).await;
         }
 
-        let link = driver
-            .find_element(By::XPath(
-                format!("//a[@href = {}]", href_click_text).as_str(),
-            ))
-            .await?;
+        Ok(posts)
+    }
+}
 
-        link.click().await?;
+async fn wait_for_articles(driver: &WebDriver) -> WebDriverResult<()> {
+    for _ in 0..LOAD_POLLS {
+        if !driver.find_elements(By::XPath(ARTICLE_XPATH)).await?.is_empty() {
+            return Ok(());
+        }
 
-        sleep(Duration::from_millis(300)).await;
+        sleep(LOAD_POLL).await;
+    }
 
-        let links = driver
-            .find_elements(By::XPath("//a[contains(@href, \"status\")]"))
-            .await?;
+    // An empty tab is not an error; there is just nothing to record.
+    Ok(())
+}
 
-        match self.record_mode {
-            RecordMode::Last => {
-                if let Some(link) = links[0 + has_pinned].get_attribute("href").await? {
-                    posts.push(link);
-                }
-            }
-            RecordMode::LastFive => {
-                if links.len() < 5 {
-                    panic!("Not enough posts!")
-                }
+pub fn profile_username(profile_url: &str) -> String {
+    profile_url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
 
-                for i in 0..5 {
-                    if let Some(link) = links[i + has_pinned].get_attribute("href").await? {
-                        posts.push(link);
-                    }
-                }
-            }
-            RecordMode::LastTen => {
-                if links.len() < 10 {
-                    panic!("Not enough posts!")
-                }
+pub fn tab_url(profile_url: &str, tweet_type: &TweetType) -> String {
+    let username = profile_username(profile_url);
 
-                for i in 0..10 {
-                    if let Some(link) = links[i + has_pinned].get_attribute("href").await? {
-                        posts.push(link);
-                    }
-                }
-            }
-            RecordMode::AllFound => {
-                for l in links {
-                    if let Some(link) = l.get_attribute("href").await? {
-                        posts.push(link);
-                    }
-                }
-            }
-        }
+    let path = match tweet_type {
+        TweetType::Post => format!("/{}", username),
+        TweetType::Reply => format!("/{}/with_replies", username),
+        TweetType::Media => format!("/{}/media", username),
+        TweetType::Likes => format!("/{}/likes", username),
+    };
 
-        Ok(posts)
-    }
+    format!("https://twitter.com{}", path)
+}
+
+/// Reads one rendered post from its `outerHTML`. Posts without a permalink, such as
+/// ads, give `None`.
+pub fn parse_article(html: &str) -> Option<TimelinePost> {
+    let permalink = RE_PERMALINK.captures(html)?;
+
+    let post_ref = PostRef::from_url(&permalink[1])?;
+
+    let created_at = DateTime::parse_from_rfc3339(&permalink[2])
+        .ok()
+        .map(|x| x.with_timezone(&Utc));
+
+    // From the social context marker up to the next test id, which is the author line.
+    let social_context = match html.split_once(SOCIAL_CONTEXT_ID) {
+        Some((_, rest)) => {
+            let context = rest.split(TEST_ID_ATTR).next().unwrap_or_default();
+
+            RE_TAG.replace_all(context, " ").to_string()
+        }
+        None => String::new(),
+    };
+
+    Some(TimelinePost {
+        post_id: post_ref.post_id,
+        username: post_ref.username,
+        created_at,
+        is_reply: html.contains("Replying to"),
+        is_retweet: social_context.contains("Retweeted") || social_context.contains("reposted"),
+        // A quoted post brings its own timestamp.
+        is_quote: html.matches("<time").count() > 1,
+        has_media: MEDIA_TEST_IDS.iter().any(|x| html.contains(x)),
+        is_pinned: social_context.contains("Pinned"),
+        media: MediaRef::from_html(html),
+    })
 }
 
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
@@ -213,6 +515,230 @@ impl SearchHeader {
 
         ret
     }
+
+    pub fn attach(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request
+            .header(USER_AGENT, self.user_agent.clone())
+            .header(REFERER, self.referer.clone())
+            .header(ACCEPT, self.accept.clone())
+            .header(CONTENT_TYPE, self.content_type.clone())
+            .header("x-twitter-auth-type", self.x_twitter_auth_type.clone())
+            .header("x-twitter-active-user", self.x_twitter_active_user.clone())
+            .header(AUTHORIZATION, self.authorization.clone())
+            .header(HOST, self.host.clone())
+            .header(COOKIE, self.cookie.clone())
+            .header("TE", self.te.clone())
+            .header("X_CSRF_TOKEN", self.x_csrf_token.clone())
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub struct PostRef {
+    pub username: String,
+    pub post_id: String,
+}
+
+impl PostRef {
+    pub fn new(username: String, post_id: String) -> Self {
+        PostRef { username, post_id }
+    }
+
+    /// Accepts full URLs as well as bare paths such as `/user/status/123/photo/1`.
+    pub fn from_url(url: &str) -> Option<Self> {
+        let trimmed = url.trim().trim_matches('"');
+        let path = trimmed.split(['?', '#']).next()?;
+
+        let segments = path
+            .split("/")
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>();
+
+        let status_at = segments.iter().position(|x| *x == "status")?;
+
+        if status_at == 0 {
+            return None;
+        }
+
+        let username = segments[status_at - 1];
+        let post_id = segments.get(status_at + 1)?;
+
+        if username.contains(".") || !post_id.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(PostRef::new(username.to_string(), post_id.to_string()))
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://twitter.com/{}/status/{}", self.username, self.post_id)
+    }
+}
+
+/// One post as it appears in a timeline response.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct TimelinePost {
+    pub post_id: String,
+    pub username: String,
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub is_reply: bool,
+    #[serde(default)]
+    pub is_retweet: bool,
+    #[serde(default)]
+    pub is_quote: bool,
+    #[serde(default)]
+    pub has_media: bool,
+    #[serde(default)]
+    pub is_pinned: bool,
+    #[serde(default)]
+    pub media: Vec<MediaRef>,
+}
+
+impl TimelinePost {
+    pub fn id_number(&self) -> Option<u64> {
+        self.post_id.parse().ok()
+    }
+
+    fn from_tweet(tweet: &Value) -> Option<Self> {
+        let post_id = tweet["rest_id"].as_str()?.to_string();
+        let legacy = &tweet["legacy"];
+
+        let username = tweet["core"]["user_results"]["result"]["legacy"]["screen_name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        let created_at = legacy["created_at"]
+            .as_str()
+            .and_then(|x| DateTime::parse_from_str(x, "%a %b %d %H:%M:%S %z %Y").ok())
+            .map(|x| x.with_timezone(&Utc));
+
+        let has_media = ["extended_entities", "entities"]
+            .iter()
+            .any(|x| legacy[x]["media"].as_array().is_some_and(|x| !x.is_empty()));
+
+        Some(TimelinePost {
+            post_id,
+            username,
+            created_at,
+            is_reply: legacy["in_reply_to_status_id_str"].is_string(),
+            is_retweet: !legacy["retweeted_status_result"].is_null(),
+            is_quote: legacy["is_quote_status"].as_bool().unwrap_or(false),
+            has_media,
+            is_pinned: false,
+            media: MediaRef::from_legacy(legacy),
+        })
+    }
+}
+
+/// Every post in a timeline response, newest first. Quoted and retweeted posts
+/// nested inside a post are not listed on their own.
+pub fn timeline_posts(page: &Value) -> Vec<TimelinePost> {
+    let mut posts = vec![];
+
+    collect_tweets(page, false, &mut posts);
+
+    posts
+}
+
+/// A pinned post comes in its own `TimelinePinEntry` instruction, and its item carries a
+/// `Pin` social context.
+fn is_pin_node(map: &serde_json::Map<String, Value>) -> bool {
+    map.get("type").and_then(|x| x.as_str()) == Some("TimelinePinEntry")
+        || map.get("socialContext").is_some_and(|x| x["contextType"] == "Pin")
+}
+
+fn collect_tweets(node: &Value, pinned: bool, posts: &mut Vec<TimelinePost>) {
+    match node {
+        Value::Object(map) => {
+            if map.get("__typename").and_then(|x| x.as_str()) == Some("Tweet") {
+                if let Some(mut post) = TimelinePost::from_tweet(node) {
+                    post.is_pinned = pinned;
+                    posts.push(post);
+                }
+
+                return;
+            }
+
+            let pinned = pinned || is_pin_node(map);
+
+            for value in map.values() {
+                collect_tweets(value, pinned, posts);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_tweets(value, pinned, posts);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The cursor that continues the timeline past this page, if there is more.
+pub fn bottom_cursor(page: &Value) -> Option<String> {
+    match page {
+        Value::Object(map) => {
+            if map.get("cursorType").and_then(|x| x.as_str()) == Some("Bottom") {
+                return map.get("value").and_then(|x| x.as_str()).map(|x| x.to_string());
+            }
+
+            map.values().find_map(bottom_cursor)
+        }
+        Value::Array(values) => values.iter().find_map(bottom_cursor),
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct Backfill {
+    pub max_pages: Option<u32>,
+    /// Start over instead of continuing from the saved cursor.
+    #[serde(default)]
+    pub restart: bool,
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct RecordProgress {
+    pub user_id: String,
+    pub cursor: Option<String>,
+    pub recorded: usize,
+    pub done: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl RecordProgress {
+    fn fresh(user_id: &str) -> Self {
+        RecordProgress {
+            user_id: user_id.to_string(),
+            cursor: None,
+            recorded: 0,
+            done: false,
+            updated_at: Utc::now(),
+        }
+    }
+
+    async fn load(db: &Database, user_id: &str) -> Self {
+        let collection = db.collection::<RecordProgress>(PROGRESS_COLL_NAME);
+
+        match collection.find_one(doc! {"user_id": user_id}, None).await {
+            Ok(Some(progress)) if !progress.done => progress,
+            _ => Self::fresh(user_id),
+        }
+    }
+
+    async fn save(&mut self, db: &Database) -> Result<(), Box<DBInsertError>> {
+        self.updated_at = Utc::now();
+
+        let options = ReplaceOptions::builder().upsert(true).build();
+
+        db.collection::<RecordProgress>(PROGRESS_COLL_NAME)
+            .replace_one(doc! {"user_id": self.user_id.as_str()}, &*self, options)
+            .await
+            .map_err(|e| DBInsertError::new(e.to_string().as_str()))?;
+
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
@@ -223,7 +749,13 @@ pub struct PostRecordRequest {
     json: String,
     record_mode: RecordMode,
     search_header: SearchHeader,
-} 
+    #[serde(default)]
+    retry: RetryPolicy,
+    #[serde(default)]
+    backfill: Backfill,
+    #[serde(default)]
+    media_archive: Option<MediaArchive>,
+}
 
 impl PostRecordRequest {
     pub fn new(
@@ -240,113 +772,101 @@ impl PostRecordRequest {
             json: String::new(),
             record_mode,
             search_header,
+            retry: RetryPolicy::default(),
+            backfill: Backfill::default(),
+            media_archive: None,
         }
     }
 
-    pub async fn get_json(&mut self) {
-        let url = make_get_post_url(self.user_id.clone(), self.count, self.link_id.clone());
+    pub fn with_backfill(mut self, backfill: Backfill) -> Self {
+        self.backfill = backfill;
 
-        let client = reqwest::Client::new();
-        let res = client
-            .get(url)
-            .header(USER_AGENT, self.search_header.clone().user_agent)
-            .header(REFERER, self.search_header.clone().referer)
-            .header(ACCEPT, self.search_header.clone().accept)
-            .header(CONTENT_TYPE, self.search_header.clone().content_type)
-            .header(
-                "x-twitter-auth-type",
-                self.search_header.clone().x_twitter_auth_type,
-            )
-            .header(
-                "x-twitter-active-user",
-                self.search_header.clone().x_twitter_active_user,
-            )
-            .header(AUTHORIZATION, self.search_header.clone().authorization)
-            .header(HOST, self.search_header.clone().host)
-            .header(COOKIE, self.search_header.clone().cookie)
-            .header("TE", self.search_header.clone().te)
-            .header("X_CSRF_TOKEN", self.search_header.clone().x_csrf_token)
-            .send()
-            .await
-            .unwrap();
-        self.json = res.text().await.unwrap();
+        self
     }
 
-    pub fn get_posts(&self) -> Vec<String> {
-        let matches = RE_POST
-            .find_iter(self.json.as_str())
-            .into_iter()
-            .collect::<Vec<_>>();
+    pub fn with_media_archive(mut self, media_archive: MediaArchive) -> Self {
+        self.media_archive = Some(media_archive);
 
-        let json_chars = self.json.split("").collect::<Vec<_>>();
+        self
+    }
 
-        let mut rets = Vec::<String>::new();
+    pub async fn get_json(&mut self, cursor: Option<String>) -> Result<(), Box<DBInsertError>> {
+        let url = make_get_post_url(self.user_id.clone(), self.count, self.link_id.clone(), cursor);
 
-        let nth: Vec<u32>;
+        let client = reqwest::Client::new();
+        let search_header = &self.search_header;
 
-        match self.record_mode {
-            RecordMode::Last => {
-                nth = vec![0];
-            }
-            RecordMode::LastFive => {
-                nth = (0..5).collect();
-            }
-            RecordMode::LastTen => {
-                nth = (0..10).collect();
-            }
-            RecordMode::AllFound => {
-                let matches_vec_len = matches.clone().len();
+        let res = with_retry(&self.retry, "get_json", || {
+            limited_send(search_header.attach(client.get(url.clone())), url.as_str(), OpKind::Api)
+        })
+        .await
+        .map_err(|e| DBInsertError::new(e.to_string().as_str()))?;
 
-                nth = (0..matches_vec_len as u32).collect();
-            }
-        }
+        self.json = res
+            .text()
+            .await
+            .map_err(|e| DBInsertError::new(e.to_string().as_str()))?;
 
-        for n in nth {
-            let mat = matches[n as usize];
-            let mut st = String::new();
-            for j in mat.start()..mat.end() {
-                st.push_str(json_chars[j]);
-            }
+        Ok(())
+    }
 
-            st = Self::extract_numbers(st);
+    /// Post ids in the last fetched page that the record mode selects.
+    pub fn get_posts(&self) -> Vec<String> {
+        let page: Value = from_str(self.json.as_str()).unwrap_or_default();
 
-            rets.push(st);
-        }
+        let (selected, _) = self.record_mode.selector().select(timeline_posts(&page), None, 0);
 
-        rets
+        selected.into_iter().map(|x| x.post_id).collect()
     }
 
-    fn extract_numbers(str: String) -> String {
-        let re_num = Regex::new(r#"\d+"#).unwrap();
+    /// Follows the bottom cursor page by page until the record mode has what it wants or
+    /// the timeline ends. The cursor is saved after every page, so an interrupted run
+    /// picks up where it stopped.
+    async fn record_pages(&mut self, db: &Database) -> Result<usize, Box<DBInsertError>> {
+        let mut progress = if self.backfill.restart {
+            RecordProgress::fresh(self.user_id.as_str())
+        } else {
+            RecordProgress::load(db, self.user_id.as_str()).await
+        };
 
-        let mut post_num = String::new();
+        let selector = self.record_mode.selector();
+        let last = last_recorded(db, self.user_id.as_str()).await;
+        let mut recorded = vec![];
+        let mut pages = 0u32;
 
-        let found = re_num.find(str.as_str()).unwrap();
+        loop {
+            self.get_json(progress.cursor.clone()).await?;
+            pages += 1;
 
-        let str_split = str.split("").into_iter().collect::<Vec<_>>();
+            let page: Value = from_str(self.json.as_str())
+                .map_err(|e| DBInsertError::new(e.to_string().as_str()))?;
 
-        for i in found.start()..found.end() {
-            post_num.push_str(str_split[i])
-        }
+            let posts = timeline_posts(&page);
+            let next = bottom_cursor(&page);
 
-        post_num
-    }
+            let ended = posts.is_empty() || next.is_none() || next == progress.cursor;
+            let (selected, satisfied) = selector.select(posts, last, progress.recorded);
 
-    fn extract_user_name(&self) -> String {
-        let mat = RE_USER_NAME.find(self.json.as_str()).unwrap();
+            store_posts(db, &selected, self.media_archive.as_ref()).await?;
 
-        let mut found = String::new();
+            progress.recorded += selected.len();
+            recorded.extend(selected);
 
-        let str_split = self.json.split("").collect::<Vec<_>>();
+            let finished = ended || satisfied;
+            let out_of_pages = self.backfill.max_pages.is_some_and(|x| pages >= x);
 
-        for i in mat.start()..mat.end() {
-            found.push_str(str_split[i]);
+            progress.cursor = next;
+            progress.done = finished;
+            progress.save(db).await?;
+
+            if finished || out_of_pages {
+                break;
+            }
         }
 
-        found = found.replace("\"screen_name\"", "");
-        found = found.replace("\"", "");
+        mark_recorded(db, self.user_id.as_str(), &recorded).await;
 
-        found
+        Ok(progress.recorded)
     }
 }
 
@@ -357,32 +877,20 @@ impl PostInDB for PostRecordScrape {
         db: &Database,
         driver: &WebDriver,
     ) -> Result<(), Box<DBInsertError>> {
-        let collection = db.collection::<Document>(&today_date_coll_name());
-
-        let mut user_name = String::new();
+        let timeline = self.profile_url.as_str();
+        let last = last_recorded(db, timeline).await;
 
-        if let Some(user_name_str) = self.profile_url.split("/").last() {
-            user_name = user_name_str.to_string();
-        }
+        let posts = self
+            .get_posts(driver, last)
+            .await
+            .map_err(|e| DBInsertError::new(e.to_string().as_str()))?;
 
-        let posts = block_on(self.get_posts(&driver))
-            .unwrap()
-            .into_iter()
-            .map(|x| {
-                if let Some(ret) = x.split("/").last() {
-                    return doc! {"username": user_name.clone(),
-                    "post": ret.to_owned()};
-                } else {
-                    panic!("Problem with url");
-                }
-            })
-            .collect::<Vec<_>>();
+        let (selected, _) = self.record_mode.selector().select(posts, last, 0);
 
-        if posts.len() == 0 {
-            return Err(DBInsertError::new("Length of posts is 0"));
-        }
+        store_posts(db, &selected, self.media_archive.as_ref()).await?;
+        mark_recorded(db, timeline, &selected).await;
 
-        collection.insert_many(posts, None).await.unwrap();
+        tracing::info!(timeline, recorded = selected.len(), "recorded posts");
 
         Ok(())
     }
@@ -391,30 +899,9 @@ impl PostInDB for PostRecordScrape {
 #[async_trait]
 impl PostInDB for PostRecordRequest {
     async fn post_in_db(&mut self, db: &Database, _: &WebDriver) -> Result<(), Box<DBInsertError>> {
-        self.get_json().await;
-
-        let collection = db.collection::<Document>(&today_date_coll_name());
-
-        let user_name = self.extract_user_name();
-
-        let posts = self
-            .get_posts()
-            .into_iter()
-            .map(|x| {
-                if let Some(ret) = x.split("/").last() {
-                    return doc! {"username": user_name.clone(),
-                    "post": ret.to_owned()};
-                } else {
-                    panic!("Problem with url");
-                }
-            })
-            .collect::<Vec<_>>();
-
-        if posts.len() == 0 {
-            return Err(DBInsertError::new("Length of posts is 0"));
-        }
+        let recorded = self.record_pages(db).await?;
 
-        collection.insert_many(posts, None).await.unwrap();
+        tracing::info!(user_id = self.user_id.as_str(), recorded, "recorded posts");
 
         Ok(())
     }
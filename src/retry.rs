@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::future::Future;
+use std::result::Result;
+use thirtyfour::error::WebDriverError;
+use tokio::time::{sleep, Duration};
+
+pub trait Retryable {
+    fn is_retryable(&self) -> bool;
+}
+
+impl Retryable for WebDriverError {
+    fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            WebDriverError::NoSuchElement(_)
+                | WebDriverError::StaleElementReference(_)
+                | WebDriverError::ElementNotInteractable(_)
+                | WebDriverError::ElementClickIntercepted(_)
+                | WebDriverError::Timeout(_)
+                | WebDriverError::HttpError(_)
+        )
+    }
+}
+
+/// Errors the browser raises before dispatching a click, so trying again cannot click
+/// twice. A timeout or a dropped connection may come after the site took the click.
+pub fn click_not_delivered(e: &WebDriverError) -> bool {
+    matches!(
+        e,
+        WebDriverError::StaleElementReference(_)
+            | WebDriverError::ElementNotInteractable(_)
+            | WebDriverError::ElementClickIntercepted(_)
+    )
+}
+
+impl<T: Retryable + ?Sized> Retryable for Box<T> {
+    fn is_retryable(&self) -> bool {
+        (**self).is_retryable()
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 1000,
+            max_delay_ms: 30000,
+            multiplier: 2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = (self.multiplier.max(1) as u64).saturating_pow(attempt.saturating_sub(1));
+        let millis = self.base_delay_ms.saturating_mul(factor).min(self.max_delay_ms);
+
+        Duration::from_millis(millis)
+    }
+}
+
+/// Runs `step` until it succeeds, fails with a permanent error or runs out of attempts.
+pub async fn with_retry<T, E, F, Fut>(policy: &RetryPolicy, step: &str, f: F) -> Result<T, E>
+where
+    E: Retryable + Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    with_retry_if(policy, step, |e: &E| e.is_retryable(), f).await
+}
+
+/// Same as `with_retry`, but only errors `retry_on` accepts are tried again.
+pub async fn with_retry_if<T, E, R, F, Fut>(
+    policy: &RetryPolicy,
+    step: &str,
+    retry_on: R,
+    mut f: F,
+) -> Result<T, E>
+where
+    E: Display,
+    R: Fn(&E) -> bool,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempt = 1u32;
+
+    loop {
+        match f().await {
+            Ok(ret) => {
+                tracing::debug!(step, attempt, "step succeeded");
+
+                return Ok(ret);
+            }
+            Err(e) => {
+                let retryable = retry_on(&e);
+
+                tracing::warn!(step, attempt, max_attempts, retryable, error = %e, "step failed");
+
+                if !retryable || attempt >= max_attempts {
+                    return Err(e);
+                }
+
+                sleep(policy.delay_for(attempt)).await;
+
+                attempt += 1;
+            }
+        }
+    }
+}
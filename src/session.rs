@@ -0,0 +1,147 @@
+use crate::config::Config;
+use crate::cookie::Cookie;
+use crate::proxy::Proxy;
+use std::sync::Arc;
+use std::time::Duration;
+use thirtyfour::prelude::WebDriverResult;
+use thirtyfour::WebDriver;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Owns the browser session. Dead sessions are replaced on the next `driver` call and
+/// the browser is quit on `shutdown`, or on drop if nobody called it.
+pub struct SessionManager {
+    proxy: Proxy,
+    config: Config,
+    driver: Option<WebDriver>,
+}
+
+impl SessionManager {
+    pub fn new(proxy: Proxy, config: Config) -> Self {
+        SessionManager {
+            proxy,
+            config,
+            driver: None,
+        }
+    }
+
+    pub async fn start(proxy: Proxy, config: Config) -> WebDriverResult<Self> {
+        let mut session = Self::new(proxy, config);
+
+        session.driver().await?;
+
+        Ok(session)
+    }
+
+    async fn is_alive(driver: &WebDriver) -> bool {
+        driver.current_url().await.is_ok()
+    }
+
+    /// The live driver, launching a new browser if there is none or the old one died.
+    pub async fn driver(&mut self) -> WebDriverResult<&WebDriver> {
+        let alive = match &self.driver {
+            Some(driver) => Self::is_alive(driver).await,
+            None => false,
+        };
+
+        if !alive {
+            if let Some(dead) = self.driver.take() {
+                tracing::warn!("webdriver session died, starting a new one");
+
+                // The browser may still be around even if the session is not answering.
+                let _ = dead.quit().await;
+            }
+
+            let driver = self.proxy.launch_driver_with_proxy(self.config.clone()).await?;
+
+            self.driver = Some(driver);
+        }
+
+        Ok(self.driver.as_ref().unwrap())
+    }
+
+    /// Saves the cookies every `cookie_refresh_minutes` until `stop` is cancelled, so they
+    /// stay fresh on disk even while no job touches the browser.
+    pub fn spawn_cookie_saver(
+        session: Arc<Mutex<Self>>,
+        stop: CancellationToken,
+    ) -> Option<JoinHandle<()>> {
+        let minutes = session.try_lock().ok()?.config.cookie_refresh_minutes?;
+        let every = Duration::from_secs(minutes.max(1) * 60);
+
+        let handle = tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+
+            loop {
+                tokio::select! {
+                    _ = stop.cancelled() => break,
+                    _ = interval.tick() => {
+                        if let Err(e) = session.lock().await.save_cookies().await {
+                            tracing::warn!(error = %e, "could not save session cookies");
+                        }
+                    }
+                }
+            }
+        });
+
+        Some(handle)
+    }
+
+    /// Writes the browser's current cookies to `Config::cookie_store`, if one is set.
+    pub async fn save_cookies(&mut self) -> WebDriverResult<usize> {
+        let (path, driver) = match (&self.config.cookie_store, &self.driver) {
+            (Some(path), Some(driver)) => (path, driver),
+            _ => return Ok(0),
+        };
+
+        let saved = Cookie::export_to_file(driver, path.as_str()).await?;
+
+        tracing::debug!(saved, path = path.as_str(), "saved session cookies");
+
+        Ok(saved)
+    }
+
+    pub async fn shutdown(&mut self) -> WebDriverResult<()> {
+        if let Err(e) = self.save_cookies().await {
+            tracing::warn!(error = %e, "could not save session cookies");
+        }
+
+        match self.driver.take() {
+            Some(driver) => driver.quit().await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SessionManager {
+    fn drop(&mut self) {
+        let driver = match self.driver.take() {
+            Some(driver) => driver,
+            None => return,
+        };
+
+        let handle = match Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                tracing::warn!("session dropped outside a runtime, browser left running");
+
+                return;
+            }
+        };
+
+        match handle.runtime_flavor() {
+            RuntimeFlavor::CurrentThread => {
+                handle.spawn(async move {
+                    let _ = driver.quit().await;
+                });
+            }
+            _ => {
+                tokio::task::block_in_place(|| {
+                    let _ = handle.block_on(driver.quit());
+                });
+            }
+        }
+    }
+}
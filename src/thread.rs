@@ -0,0 +1,413 @@
+use crate::rate_limit::{limited_send, OpKind};
+use crate::record_posts::{DBInsertError, PostInDB, PostRef, SearchHeader};
+use crate::retry::{with_retry, RetryPolicy};
+use crate::utils::make_get_post_detail_url;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use mongodb::options::ReplaceOptions;
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::result::Result;
+use thirtyfour::WebDriver;
+
+const THREAD_COLL_NAME: &str = "thread-posts";
+const MAX_REQUESTS: u32 = 50;
+/// Cursors that load more of the conversation. `Top` only goes back to posts already seen.
+const MORE_CURSORS: [&str; 3] = ["Bottom", "ShowMore", "ShowMoreThreads"];
+
+/// One post of a conversation, stored with the post it answers.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct ThreadPost {
+    pub post_id: String,
+    pub username: String,
+    pub conversation_id: String,
+    pub parent_id: Option<String>,
+    pub text: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl ThreadPost {
+    fn from_tweet(tweet: &Value) -> Option<Self> {
+        let legacy = &tweet["legacy"];
+        let post_id = tweet["rest_id"].as_str()?.to_string();
+
+        Some(ThreadPost {
+            conversation_id: legacy["conversation_id_str"]
+                .as_str()
+                .unwrap_or(post_id.as_str())
+                .to_string(),
+            post_id,
+            username: tweet["core"]["user_results"]["result"]["legacy"]["screen_name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            parent_id: legacy["in_reply_to_status_id_str"].as_str().map(|x| x.to_string()),
+            text: legacy["full_text"].as_str().unwrap_or_default().to_string(),
+            created_at: legacy["created_at"]
+                .as_str()
+                .and_then(|x| DateTime::parse_from_str(x, "%a %b %d %H:%M:%S %z %Y").ok())
+                .map(|x| x.with_timezone(&Utc)),
+        })
+    }
+
+    fn sort_key(&self) -> (Option<DateTime<Utc>>, u64) {
+        (self.created_at, self.post_id.parse().unwrap_or_default())
+    }
+}
+
+/// Every post in a TweetDetail response. Quoted posts are not part of the conversation
+/// and are left out.
+pub fn thread_posts(page: &Value) -> Vec<ThreadPost> {
+    let mut posts = vec![];
+
+    collect_posts(page, &mut posts);
+
+    posts
+}
+
+fn collect_posts(node: &Value, posts: &mut Vec<ThreadPost>) {
+    match node {
+        Value::Object(map) => {
+            if map.get("__typename").and_then(|x| x.as_str()) == Some("Tweet") {
+                if let Some(post) = ThreadPost::from_tweet(node) {
+                    posts.push(post);
+                }
+
+                return;
+            }
+
+            for value in map.values() {
+                collect_posts(value, posts);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_posts(value, posts);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The cursors in a TweetDetail response that load more replies: the bottom of the
+/// conversation and the "show more replies" buttons.
+pub fn thread_cursors(page: &Value) -> Vec<String> {
+    let mut cursors = vec![];
+
+    collect_cursors(page, &mut cursors);
+
+    cursors
+}
+
+fn collect_cursors(node: &Value, cursors: &mut Vec<String>) {
+    match node {
+        Value::Object(map) => {
+            let kind = map.get("cursorType").and_then(|x| x.as_str()).unwrap_or_default();
+
+            if MORE_CURSORS.contains(&kind) {
+                if let Some(value) = map.get("value").and_then(|x| x.as_str()) {
+                    cursors.push(value.to_string());
+                }
+
+                return;
+            }
+
+            for value in map.values() {
+                collect_cursors(value, cursors);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_cursors(value, cursors);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Places the posts of one page relative to the recorded post, which `depths` holds at 0
+/// along with everything placed from earlier pages. Replies get their level below it and
+/// ancestors a negative one. Other branches of the conversation are left out.
+pub fn place_posts(posts: &[ThreadPost], depths: &mut HashMap<String, i64>) {
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for post in posts {
+            let depth = depths.get(&post.post_id).copied();
+            let parent = post.parent_id.as_ref();
+            let parent_depth = parent.and_then(|x| depths.get(x)).copied();
+
+            match (depth, parent, parent_depth) {
+                (None, _, Some(parent_depth)) if parent_depth >= 0 => {
+                    depths.insert(post.post_id.clone(), parent_depth + 1);
+                    changed = true;
+                }
+                (Some(depth), Some(parent), None)
+                    if depth <= 0 && posts.iter().any(|x| &x.post_id == parent) =>
+                {
+                    depths.insert(parent.clone(), depth - 1);
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct ThreadNode {
+    pub post: ThreadPost,
+    pub replies: Vec<ThreadNode>,
+}
+
+impl ThreadNode {
+    pub fn size(&self) -> usize {
+        1 + self.replies.iter().map(|x| x.size()).sum::<usize>()
+    }
+}
+
+/// Arranges stored posts into a tree under `root_id`. When the root itself was never
+/// recorded, the oldest post whose parent is missing stands in for it.
+pub fn build_tree(posts: Vec<ThreadPost>, root_id: &str) -> Option<ThreadNode> {
+    let ids: HashSet<String> = posts.iter().map(|x| x.post_id.clone()).collect();
+
+    let root_id = if ids.contains(root_id) {
+        root_id.to_string()
+    } else {
+        posts
+            .iter()
+            .filter(|x| x.parent_id.as_ref().is_none_or(|p| !ids.contains(p)))
+            .min_by_key(|x| x.sort_key())?
+            .post_id
+            .clone()
+    };
+
+    let mut children: HashMap<String, Vec<ThreadPost>> = HashMap::new();
+    let mut root = None;
+
+    for post in posts {
+        if post.post_id == root_id {
+            root = Some(post);
+        } else if let Some(parent) = post.parent_id.clone() {
+            children.entry(parent).or_default().push(post);
+        }
+    }
+
+    Some(attach_replies(root?, &mut children))
+}
+
+fn attach_replies(post: ThreadPost, children: &mut HashMap<String, Vec<ThreadPost>>) -> ThreadNode {
+    let mut replies = children.remove(&post.post_id).unwrap_or_default();
+
+    replies.sort_by_key(|x| x.sort_key());
+
+    ThreadNode {
+        replies: replies
+            .into_iter()
+            .map(|x| attach_replies(x, children))
+            .collect(),
+        post,
+    }
+}
+
+#[derive(Debug)]
+pub struct ThreadError {
+    details: String,
+}
+
+impl fmt::Display for ThreadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for ThreadError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl ThreadError {
+    pub fn new(details: &str) -> Box<Self> {
+        let err = ThreadError {
+            details: details.to_string(),
+        };
+
+        Box::from(err)
+    }
+}
+
+impl From<mongodb::error::Error> for Box<ThreadError> {
+    fn from(e: mongodb::error::Error) -> Self {
+        ThreadError::new(e.to_string().as_str())
+    }
+}
+
+pub struct ThreadStore {
+    posts: Collection<ThreadPost>,
+}
+
+impl ThreadStore {
+    pub fn new(db: &Database) -> Self {
+        ThreadStore {
+            posts: db.collection::<ThreadPost>(THREAD_COLL_NAME),
+        }
+    }
+
+    pub async fn store(&self, post: &ThreadPost) -> Result<(), Box<ThreadError>> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+
+        self.posts
+            .replace_one(doc! {"post_id": post.post_id.as_str()}, post, options)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The whole conversation `post_id` belongs to, as recorded so far.
+    pub async fn tree(&self, post_id: &str) -> Result<Option<ThreadNode>, Box<ThreadError>> {
+        let conversation_id = match self.posts.find_one(doc! {"post_id": post_id}, None).await? {
+            Some(post) => post.conversation_id,
+            None => return Ok(None),
+        };
+
+        let cursor = self
+            .posts
+            .find(doc! {"conversation_id": conversation_id.as_str()}, None)
+            .await?;
+        let posts: Vec<ThreadPost> = cursor.try_collect().await?;
+
+        Ok(build_tree(posts, conversation_id.as_str()))
+    }
+}
+
+/// Records the conversation around `post`: its ancestors up to the root, and replies
+/// down to `max_depth` levels below it.
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub struct ThreadRecorder {
+    post: PostRef,
+    link_id: String,
+    search_header: SearchHeader,
+    max_depth: u32,
+    max_requests: Option<u32>,
+    #[serde(default)]
+    retry: RetryPolicy,
+}
+
+impl ThreadRecorder {
+    pub fn from_json_str(json_str: String) -> Self {
+        let ret: ThreadRecorder = from_str(json_str.as_str()).unwrap();
+
+        ret
+    }
+
+    pub fn post(&self) -> &PostRef {
+        &self.post
+    }
+
+    async fn fetch_detail(
+        &self,
+        post_id: &str,
+        cursor: Option<String>,
+    ) -> Result<Value, Box<ThreadError>> {
+        let url = make_get_post_detail_url(post_id.to_string(), self.link_id.clone(), cursor);
+
+        let client = reqwest::Client::new();
+
+        let res = with_retry(&self.retry, post_id, || {
+            limited_send(
+                self.search_header.attach(client.get(url.clone())),
+                url.as_str(),
+                OpKind::Api,
+            )
+        })
+        .await
+        .map_err(|e| ThreadError::new(e.to_string().as_str()))?;
+
+        let text = res
+            .text()
+            .await
+            .map_err(|e| ThreadError::new(e.to_string().as_str()))?;
+
+        from_str::<Value>(text.as_str()).map_err(|e| ThreadError::new(e.to_string().as_str()))
+    }
+
+    /// Walks the replies breadth first, one TweetDetail request per post that may still
+    /// have replies within `max_depth`, plus one per "show more" or bottom cursor. Only
+    /// the ancestors and replies down to `max_depth` are stored. Returns how many were.
+    pub async fn record(&self, db: &Database) -> Result<usize, Box<ThreadError>> {
+        let store = ThreadStore::new(db);
+        let max_requests = self.max_requests.unwrap_or(MAX_REQUESTS);
+        let max_depth = self.max_depth as i64;
+
+        let mut queue = VecDeque::from([(self.post.post_id.clone(), None)]);
+        let mut queued = HashSet::from([self.post.post_id.clone()]);
+        let mut cursors = HashSet::<String>::new();
+        let mut depths = HashMap::from([(self.post.post_id.clone(), 0i64)]);
+        let mut stored = HashSet::<String>::new();
+        let mut requests = 0;
+
+        while let Some((post_id, cursor)) = queue.pop_front() {
+            if requests >= max_requests {
+                tracing::warn!(post_id, max_requests, "thread request limit reached");
+
+                break;
+            }
+
+            let page = self.fetch_detail(post_id.as_str(), cursor).await?;
+            requests += 1;
+
+            let posts = thread_posts(&page);
+
+            place_posts(&posts, &mut depths);
+
+            for post in posts {
+                let depth = match depths.get(&post.post_id) {
+                    Some(depth) if *depth <= max_depth => *depth,
+                    _ => continue,
+                };
+
+                if stored.insert(post.post_id.clone()) {
+                    store.store(&post).await?;
+                }
+
+                if depth > 0 && depth < max_depth && queued.insert(post.post_id.clone()) {
+                    queue.push_back((post.post_id, None));
+                }
+            }
+
+            // More replies to the same post, behind a cursor.
+            if depths.get(&post_id).is_some_and(|x| *x < max_depth) {
+                for cursor in thread_cursors(&page) {
+                    if cursors.insert(cursor.clone()) {
+                        queue.push_back((post_id.clone(), Some(cursor)));
+                    }
+                }
+            }
+        }
+
+        Ok(stored.len())
+    }
+}
+
+#[async_trait]
+impl PostInDB for ThreadRecorder {
+    async fn post_in_db(&mut self, db: &Database, _: &WebDriver) -> Result<(), Box<DBInsertError>> {
+        let recorded = self
+            .record(db)
+            .await
+            .map_err(|e| DBInsertError::new(e.to_string().as_str()))?;
+
+        tracing::info!(post = self.post.url().as_str(), recorded, "recorded thread");
+
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use rand::{self, Rng};
 use std::fs::read_to_string;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
+use tempfile::NamedTempFile;
+use thirtyfour::error::WebDriverError;
 use zip::write::FileOptions;
 
 lazy_static! {
@@ -19,6 +22,33 @@ pub fn write_to_file(fname: &str, message: String) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Writes next to `fname` first and renames over it, so readers never see half a file.
+pub fn write_to_file_atomic(fname: &str, message: String) -> std::io::Result<()> {
+    write_bytes_atomic(fname, message.as_bytes())
+}
+
+/// Writes to a temp file next to `fname` and renames it over, so readers never see half a
+/// file. The temp name is unique, so two writers cannot clobber each other's temp file.
+pub fn write_bytes_atomic(fname: &str, bytes: &[u8]) -> std::io::Result<()> {
+    let dir = match Path::new(fname).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut tmp = NamedTempFile::new_in(dir)?;
+
+    tmp.write_all(bytes)?;
+    tmp.persist(fname).map_err(|e| e.error)?;
+
+    Ok(())
+}
+
+/// `WebDriverError` has no variant for the bot's own failures, such as a blocked action
+/// or a bad file, so they are reported as `RequestFailed`.
+pub fn bot_error(details: String) -> WebDriverError {
+    WebDriverError::RequestFailed(details)
+}
+
 pub fn read_from_file(fname: &str) -> std::io::Result<String> {
     let contents = read_to_string(fname)?;
 
@@ -26,11 +56,7 @@ pub fn read_from_file(fname: &str) -> std::io::Result<String> {
 }
 
 pub fn convert_timestamp(timestamp: i64) -> DateTime<Utc> {
-    let naive = NaiveDateTime::from_timestamp(timestamp, 0);
-
-    let datetime: DateTime<Utc> = DateTime::from_utc(naive, Utc);
-
-    datetime
+    DateTime::from_timestamp(timestamp, 0).unwrap_or_default()
 }
 
 pub fn rand_num_wait() -> u8 {
@@ -43,7 +69,7 @@ pub fn write_strings_to_zip(
     manifest: String,
 ) -> zip::result::ZipResult<()> {
     let path = std::path::Path::new(filename.as_str());
-    let file = std::fs::File::create(&path).unwrap();
+    let file = std::fs::File::create(path)?;
 
     let mut zip = zip::ZipWriter::new(file);
 
@@ -51,36 +77,82 @@ pub fn write_strings_to_zip(
         .compression_method(zip::CompressionMethod::Stored)
         .unix_permissions(0o755);
 
-    zip.start_file(format!("background.js"), options)?;
+    zip.start_file("background.js", options)?;
     zip.write_all(background.as_bytes())?;
 
-    zip.start_file(format!("manifest.json"), options)?;
+    zip.start_file("manifest.json", options)?;
     zip.write_all(manifest.as_bytes())?;
 
     Ok(())
 }
 
+/// The collection posts recorded on `date` go to.
+pub fn date_coll_name(date: NaiveDate) -> String {
+    format!("{}-posts", date.format("%Y-%m-%d"))
+}
+
 pub fn today_date_coll_name() -> String {
     let now = Utc::now();
 
-    let mut ret = now.date().format("%Y-%m-%d").to_string();
+    let mut ret = now.date_naive().format("%Y-%m-%d").to_string();
 
     ret.push_str("-posts");
 
     ret
 }
 
-pub fn make_get_post_url(id: String, count: u32, linkid: String) -> String {
+pub fn make_get_post_url(id: String, count: u32, linkid: String, cursor: Option<String>) -> String {
     let domain_id = format!(
         "https://twitter.com/i/api/graphql/{}/UserTweets?variables=",
         linkid
     );
     let params_main = format!(
-        "%7B%22userId%22%3A%{}%22%2C%22count%22%3A{}%2C%22",
+        "%7B%22userId%22%3A%22{}%22%2C%22count%22%3A{}%2C%22",
         id, count
     );
+    let params_cursor = match cursor {
+        Some(cursor) => format!("cursor%22%3A%22{}%22%2C%22", percent_encode(cursor.as_str())),
+        None => String::new(),
+    };
+
+    let fin = format!(
+        "{}{}{}{}",
+        domain_id,
+        params_main,
+        params_cursor,
+        REMAINDER_STR.trim()
+    );
+
+    fin
+}
+
+/// Percent-encodes everything except RFC 3986 unreserved characters.
+pub fn percent_encode(raw: &str) -> String {
+    raw.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub fn make_get_post_detail_url(post_id: String, linkid: String, cursor: Option<String>) -> String {
+    let domain_id = format!(
+        "https://twitter.com/i/api/graphql/{}/TweetDetail?variables=",
+        linkid
+    );
+    let params_main = format!(
+        "%7B%22focalTweetId%22%3A%22{}%22%2C%22with_rux_injections%22%3Afalse%2C%22",
+        post_id
+    );
+    let params_cursor = match cursor {
+        Some(cursor) => format!("cursor%22%3A%22{}%22%2C%22", percent_encode(cursor.as_str())),
+        None => String::new(),
+    };
 
-    let fin = format!("{}{}{}", domain_id, params_main, REMAINDER_STR.clone()).to_string();
+    let fin = format!("{}{}{}{}", domain_id, params_main, params_cursor, REMAINDER_STR.trim());
 
     fin
 }